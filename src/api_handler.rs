@@ -18,20 +18,24 @@
 
 use crate::{
     config::{Config, Feature},
+    internal_id::{InternalId, ItemInfo},
+    metrics::{inc, observe},
     statics::*,
+    storage::{get_snapshot, Dataset},
     structs::*,
     utils::*,
 };
 use dashmap::{DashMap, DashSet};
 use futures::{stream::FuturesUnordered, FutureExt, StreamExt};
 use log::{debug, info};
+use serde::de::DeserializeOwned;
 use serde_json::{json, Value};
 use std::{
-    fs,
     sync::{Arc, Mutex},
     thread,
     time::{Duration, Instant},
 };
+use tokio::fs;
 
 /// Update the enabled APIs
 pub async fn update_auctions(config: Arc<Config>) -> bool {
@@ -53,23 +57,47 @@ pub async fn update_auctions(config: Arc<Config>) -> bool {
     let inserted_uuids: DashSet<String> = DashSet::new();
     let query_prices: Mutex<Vec<QueryDatabaseItem>> = Mutex::new(Vec::new());
     let pet_prices: DashMap<String, AvgSum> = DashMap::new();
-    let bin_prices: DashMap<String, f32> = DashMap::new();
+    let pet_sales: Mutex<Vec<PetSalesDatabaseItem>> = Mutex::new(Vec::new());
+    let bin_prices: DashMap<String, LowestBin> = DashMap::new();
     let under_bin_prices: DashMap<String, Value> = DashMap::new();
     let avg_ah_prices: DashMap<String, AvgSum> = DashMap::new();
     let avg_bin_prices: DashMap<String, AvgSum> = DashMap::new();
-    let past_bin_prices: DashMap<String, f32> = serde_json::from_str(
-        &fs::read_to_string("lowestbin.json").unwrap_or_else(|_| String::from("{}")),
-    )
-    .unwrap();
+    let sale_prices: Mutex<Vec<SalesDatabaseItem>> = Mutex::new(Vec::new());
+    let past_bin_prices: DashMap<String, f32> =
+        match get_snapshot(config.storage, Dataset::Lowestbin).await {
+            Ok(Some(snapshot)) => serde_json::from_slice(&snapshot.data).unwrap_or_default(),
+            _ => DashMap::new(),
+        };
     let ended_auction_uuids: DashSet<String> = DashSet::new();
+    // Auctions that were skipped because their data could not be parsed (uuid to reason)
+    let ingest_errors: DashMap<String, String> = DashMap::new();
+
+    // Directory to record the raw pages of this update into (if recording is enabled)
+    let record_dir = if !config.record_dir.is_empty() && config.replay_dir.is_empty() {
+        let record_dir = format!("{}/{}", config.record_dir, started_epoch);
+        match fs::create_dir_all(&record_dir).await {
+            Ok(_) => Some(record_dir),
+            Err(e) => {
+                error(format!(
+                    "Failed to create record directory {}: {}",
+                    record_dir, e
+                ));
+                None
+            }
+        }
+    } else {
+        None
+    };
 
     // Get which APIs to update
     let update_query = config.is_enabled(Feature::Query);
     let update_pets = config.is_enabled(Feature::Pets);
     let update_lowestbin = config.is_enabled(Feature::Lowestbin);
     let update_underbin = config.is_enabled(Feature::Underbin);
+    let update_lowestbin_history = config.is_enabled(Feature::LowestbinHistory);
     let update_average_auction = config.is_enabled(Feature::AverageAuction);
     let update_average_bin = config.is_enabled(Feature::AverageBin);
+    let update_sales = config.is_enabled(Feature::Sales);
 
     // Stores the futures for all auction pages in order to utilize multithreading
     let futures = FuturesUnordered::new();
@@ -77,7 +105,7 @@ pub async fn update_auctions(config: Arc<Config>) -> bool {
     // Only fetch auctions if any of APIs that need the auctions are enabled
     if update_query || update_lowestbin || update_underbin {
         // First page to get the total number of pages
-        let json_opt = get_auction_page(0).await;
+        let json_opt = get_auction_page(&config, record_dir.as_deref(), 0).await;
         if json_opt.is_none() {
             error(String::from(
                 "Failed to fetch the first auction page. Canceling this run.",
@@ -102,6 +130,7 @@ pub async fn update_auctions(config: Arc<Config>) -> bool {
             &bin_prices,
             &under_bin_prices,
             &past_bin_prices,
+            &ingest_errors,
             update_query,
             update_lowestbin,
             update_underbin,
@@ -114,12 +143,15 @@ pub async fn update_auctions(config: Arc<Config>) -> bool {
             for page_number in 1..json.total_pages {
                 futures.push(
                     process_auction_page(
+                        &config,
+                        record_dir.as_deref(),
                         page_number,
                         &inserted_uuids,
                         &query_prices,
                         &bin_prices,
                         &under_bin_prices,
                         &past_bin_prices,
+                        &ingest_errors,
                         update_query,
                         update_lowestbin,
                         update_underbin,
@@ -131,12 +163,15 @@ pub async fn update_auctions(config: Arc<Config>) -> bool {
         } else if !finished {
             for page_number in 1..json.total_pages {
                 if process_auction_page(
+                    &config,
+                    record_dir.as_deref(),
                     page_number,
                     &inserted_uuids,
                     &query_prices,
                     &bin_prices,
                     &under_bin_prices,
                     &past_bin_prices,
+                    &ingest_errors,
                     update_query,
                     update_lowestbin,
                     update_underbin,
@@ -151,15 +186,26 @@ pub async fn update_auctions(config: Arc<Config>) -> bool {
     }
 
     // Update average auctions if the feature is enabled
-    if update_average_auction || update_average_bin || update_pets || !is_full_update {
+    if update_average_auction
+        || update_average_bin
+        || update_pets
+        || update_sales
+        || !is_full_update
+    {
         futures.push(
             parse_ended_auctions(
+                &config,
+                record_dir.as_deref(),
                 &avg_ah_prices,
                 &avg_bin_prices,
                 &pet_prices,
+                &pet_sales,
+                &sale_prices,
+                &ingest_errors,
                 update_average_auction,
                 update_average_bin,
                 update_pets,
+                update_sales,
                 &ended_auction_uuids,
                 !is_full_update,
                 &mut started_epoch,
@@ -172,6 +218,22 @@ pub async fn update_auctions(config: Arc<Config>) -> bool {
 
     let fetch_sec = started.elapsed().as_secs_f32();
     info!("Total fetch time: {:.2}s", fetch_sec);
+    observe(
+        "query_api_update_seconds",
+        &[("phase", "fetch")],
+        fetch_sec as f64,
+    );
+
+    if !ingest_errors.is_empty() {
+        error(format!(
+            "Skipped {} auctions with malformed data (see /ingest_errors)",
+            ingest_errors.len()
+        ));
+    }
+    *INGEST_ERRORS.lock().await = ingest_errors
+        .into_iter()
+        .map(|(uuid, reason)| IngestError { uuid, reason })
+        .collect();
 
     debug!("Inserting into database");
     let insert_started = Instant::now();
@@ -192,13 +254,36 @@ pub async fn update_auctions(config: Arc<Config>) -> bool {
                 last_updated,
                 update_underbin,
                 &under_bin_prices,
+                config.storage,
+                update_lowestbin_history,
+                config.lowestbin_history_retention,
+                started_epoch,
             )
             .boxed(),
         );
     }
 
     if update_pets && !pet_prices.is_empty() {
-        insert_futures.push(update_pets_fn(pet_prices).boxed());
+        insert_futures.push(
+            update_average_fn(
+                "average pets",
+                "average_pets",
+                pet_prices,
+                started_epoch,
+                config.pets_retention,
+            )
+            .boxed(),
+        );
+    }
+
+    if update_pets && !pet_sales.lock().unwrap().is_empty() {
+        insert_futures
+            .push(update_pet_sales_fn(pet_sales, config.pets_retention, started_epoch).boxed());
+    }
+
+    if update_sales && !sale_prices.lock().unwrap().is_empty() {
+        insert_futures
+            .push(update_sales_fn(sale_prices, config.sales_retention, started_epoch).boxed());
     }
 
     if update_average_auction && !avg_ah_prices.is_empty() {
@@ -208,6 +293,7 @@ pub async fn update_auctions(config: Arc<Config>) -> bool {
                 "average_auction",
                 avg_ah_prices,
                 started_epoch,
+                7,
             )
             .boxed(),
         );
@@ -215,7 +301,14 @@ pub async fn update_auctions(config: Arc<Config>) -> bool {
 
     if update_average_bin && !avg_bin_prices.is_empty() {
         insert_futures.push(
-            update_average_fn("average bins", "average_bin", avg_bin_prices, started_epoch).boxed(),
+            update_average_fn(
+                "average bins",
+                "average_bin",
+                avg_bin_prices,
+                started_epoch,
+                7,
+            )
+            .boxed(),
         );
     }
 
@@ -246,7 +339,18 @@ pub async fn update_auctions(config: Arc<Config>) -> bool {
         insert_started.elapsed().as_secs_f32(),
         started.elapsed().as_secs_f32()
     ));
-
+    observe(
+        "query_api_update_seconds",
+        &[("phase", "insert")],
+        insert_started.elapsed().as_secs_f64(),
+    );
+    observe(
+        "query_api_update_seconds",
+        &[("phase", "total")],
+        started.elapsed().as_secs_f64(),
+    );
+
+    inc("query_api_updates_total", &[], 1.0);
     *TOTAL_UPDATES.lock().await += 1;
     *LAST_UPDATED.lock().await = started_epoch;
     *IS_UPDATING.lock().await = false;
@@ -255,12 +359,15 @@ pub async fn update_auctions(config: Arc<Config>) -> bool {
 }
 
 async fn process_auction_page(
+    config: &Config,
+    record_dir: Option<&str>,
     page_number: i32,
     inserted_uuids: &DashSet<String>,
     query_prices: &Mutex<Vec<QueryDatabaseItem>>,
-    bin_prices: &DashMap<String, f32>,
+    bin_prices: &DashMap<String, LowestBin>,
     under_bin_prices: &DashMap<String, Value>,
     past_bin_prices: &DashMap<String, f32>,
+    ingest_errors: &DashMap<String, String>,
     update_query: bool,
     update_lowestbin: bool,
     update_underbin: bool,
@@ -268,7 +375,7 @@ async fn process_auction_page(
 ) -> bool {
     let before_page_request = Instant::now();
     // Get the page from the Hypixel API
-    if let Some(page_request) = get_auction_page(page_number).await {
+    if let Some(page_request) = get_auction_page(config, record_dir, page_number).await {
         debug!("---------------- Fetching page {}", page_request.page);
         debug!(
             "Request time: {}ms",
@@ -284,6 +391,7 @@ async fn process_auction_page(
             bin_prices,
             under_bin_prices,
             past_bin_prices,
+            ingest_errors,
             update_query,
             update_lowestbin,
             update_underbin,
@@ -310,144 +418,80 @@ fn parse_auctions(
     auctions: Vec<Auction>,
     inserted_uuids: &DashSet<String>,
     query_prices: &Mutex<Vec<QueryDatabaseItem>>,
-    bin_prices: &DashMap<String, f32>,
+    bin_prices: &DashMap<String, LowestBin>,
     under_bin_prices: &DashMap<String, Value>,
     past_bin_prices: &DashMap<String, f32>,
+    ingest_errors: &DashMap<String, String>,
     update_query: bool,
     update_lowestbin: bool,
     update_underbin: bool,
     last_updated: i64,
 ) -> bool {
     let is_full_update = last_updated == 0;
+    let parse_started = Instant::now();
+    let mut parsed = 0;
+    let mut is_finished = false;
 
     for auction in auctions {
         if !is_full_update && last_updated >= auction.last_updated {
-            return true;
+            is_finished = true;
+            break;
         }
 
         // Prevent duplicate auctions (returns false if already exists)
         if inserted_uuids.insert(auction.uuid.to_string()) {
             let mut tier = auction.tier;
 
-            let nbt = &parse_nbt(&auction.item_bytes).unwrap().i[0];
+            let nbt = match parse_item(&auction.item_bytes) {
+                Ok(nbt) => nbt,
+                Err(e) => {
+                    inc("query_api_nbt_errors_total", &[], 1.0);
+                    ingest_errors.insert(auction.uuid, e);
+                    continue;
+                }
+            };
             let extra_attrs = &nbt.tag.extra_attributes;
             let id = extra_attrs.id.to_owned();
-            let mut lowestbin_id = id.to_owned();
-            let mut lowestbin_price = auction.starting_bid as f32 / nbt.count as f32;
 
-            let mut enchants = Vec::new();
-            let mut attributes = Vec::new();
-            if update_query {
-                if let Some(enchantments) = &extra_attrs.enchantments {
-                    for entry in enchantments {
-                        enchants.push(format!("{};{}", entry.key().to_uppercase(), entry.value()));
+            let mut pet_info = None;
+            if id == "PET" {
+                match parse_pet_info(extra_attrs) {
+                    // If the pet is tier boosted, the tier field in the auction shows the rarity after boosting
+                    Ok(pet_info_parsed) => {
+                        tier = pet_info_parsed.tier.to_owned();
+                        pet_info = Some(pet_info_parsed);
                     }
-                }
-                if let Some(attributes_unwrap) = &extra_attrs.attributes {
-                    for entry in attributes_unwrap {
-                        attributes.push(format!(
-                            "ATTRIBUTE_SHARD_{};{}",
-                            entry.0.to_uppercase(),
-                            entry.1
-                        ));
+                    Err(e) => {
+                        ingest_errors.insert(auction.uuid, e);
+                        continue;
                     }
                 }
             }
 
-            if id == "PET" {
-                // If the pet is tier boosted, the tier field in the auction shows the rarity after boosting
-                tier = serde_json::from_str::<PetInfo>(extra_attrs.pet.as_ref().unwrap())
-                    .unwrap()
-                    .tier;
-
-                if auction.bin && update_lowestbin {
-                    let mut split = auction.item_name.split("] ");
-                    split.next();
-
-                    if let Some(pet_name) = split.next() {
-                        lowestbin_id = format!(
-                            "{};{}",
-                            pet_name.replace(' ', "_").replace("_✦", "").to_uppercase(),
-                            match tier.as_str() {
-                                "COMMON" => 0,
-                                "UNCOMMON" => 1,
-                                "RARE" => 2,
-                                "EPIC" => 3,
-                                "LEGENDARY" => 4,
-                                "MYTHIC" => 5,
-                                _ => -1,
-                            }
-                        );
-                    }
+            let internal_id = match InternalId::new(&ItemInfo {
+                extra_attrs,
+                pet_info: pet_info.as_ref(),
+                item_name: &auction.item_name,
+            }) {
+                Ok(internal_id) => internal_id,
+                Err(e) => {
+                    ingest_errors.insert(auction.uuid, e);
+                    continue;
                 }
-            }
+            };
+            let lowestbin_id = internal_id.id;
+            let lowestbin_price =
+                auction.starting_bid as f32 / nbt.count as f32 / internal_id.price_divisor as f32;
 
             if auction.bin && update_lowestbin {
-                if let Some(attributes) = &extra_attrs.attributes {
-                    if id == "ATTRIBUTE_SHARD" {
-                        if attributes.len() == 1 {
-                            for entry in attributes {
-                                lowestbin_id = format!("{}_{}", id, entry.0.to_uppercase());
-                                lowestbin_price /= 2_i64.pow((entry.1 - 1) as u32) as f32;
-                            }
-                        }
-                    } else {
-                        for entry in attributes {
-                            lowestbin_id.push_str("+ATTRIBUTE_SHARD_");
-                            lowestbin_id.push_str(&entry.0.to_uppercase());
-                        }
-                    }
-                }
-                if id == "PARTY_HAT_CRAB" || id == "PARTY_HAT_CRAB_ANIMATED" {
-                    if let Some(party_hat_color) = &extra_attrs.party_hat_color {
-                        lowestbin_id = format!(
-                            "PARTY_HAT_CRAB_{}{}",
-                            party_hat_color.to_uppercase(),
-                            if id.ends_with("_ANIMATED") {
-                                "_ANIMATED"
-                            } else {
-                                ""
-                            }
-                        );
-                    }
-                } else if id == "PARTY_HAT_SLOTH" {
-                    if let Some(party_hat_emoji) = &extra_attrs.party_hat_emoji {
-                        lowestbin_id = format!("{}_{}", id, party_hat_emoji.to_uppercase());
-                    }
-                } else if id == "NEW_YEAR_CAKE" {
-                    if let Some(new_years_cake) = &extra_attrs.new_years_cake {
-                        lowestbin_id = format!("{}_{}", id, new_years_cake);
-                    }
-                } else if id == "MIDAS_SWORD" || id == "MIDAS_STAFF" {
-                    if let Some(winning_bid) = &extra_attrs.winning_bid {
-                        let best_bid = if id == "MIDAS_SWORD" {
-                            50000000
-                        } else {
-                            100000000
-                        };
-                        if winning_bid > &best_bid {
-                            lowestbin_id = format!("{}_{}", id, best_bid);
-                        }
-                    }
-                } else if id == "RUNE" {
-                    if let Some(runes) = &extra_attrs.runes {
-                        if runes.len() == 1 {
-                            for entry in runes {
-                                lowestbin_id = format!(
-                                    "{}_RUNE;{}",
-                                    entry.key().to_uppercase(),
-                                    entry.value()
-                                );
-                            }
-                        }
-                    }
-                }
-                if extra_attrs.is_shiny() {
-                    lowestbin_id.push_str("_SHINY");
-                }
-
                 if is_full_update {
-                    update_lower_else_insert(&lowestbin_id, lowestbin_price, bin_prices);
+                    update_lower_else_insert(
+                        &lowestbin_id,
+                        lowestbin_price,
+                        &auction.uuid,
+                        &auction.auctioneer,
+                        bin_prices,
+                    );
                 }
 
                 if update_underbin
@@ -500,8 +544,8 @@ fn parse_auctions(
                     lowestbin_price,
                     item_id: id,
                     internal_id: lowestbin_id,
-                    enchants,
-                    attributes,
+                    enchants: extra_attrs.get_enchants(),
+                    attributes: extra_attrs.get_attributes(),
                     bin: auction.bin,
                     bids,
                     count: nbt.count,
@@ -528,35 +572,56 @@ fn parse_auctions(
                     gemstones: extra_attrs.get_gemstones(),
                 });
             }
+            parsed += 1;
         }
     }
 
-    false
+    observe(
+        "query_api_page_seconds",
+        &[("endpoint", "auctions"), ("stage", "parse")],
+        parse_started.elapsed().as_secs_f64(),
+    );
+    inc(
+        "query_api_auctions_parsed_total",
+        &[("kind", "active")],
+        parsed as f64,
+    );
+
+    is_finished
 }
 
 /* Parse ended auctions into Vec<AvgAh> */
 async fn parse_ended_auctions(
+    config: &Config,
+    record_dir: Option<&str>,
     avg_ah_prices: &DashMap<String, AvgSum>,
     avg_bin_prices: &DashMap<String, AvgSum>,
     pet_prices: &DashMap<String, AvgSum>,
+    pet_sales: &Mutex<Vec<PetSalesDatabaseItem>>,
+    sale_prices: &Mutex<Vec<SalesDatabaseItem>>,
+    ingest_errors: &DashMap<String, String>,
     update_average_auction: bool,
     update_average_bin: bool,
     update_pets: bool,
+    update_sales: bool,
     ended_auction_uuids: &DashSet<String>,
     update_ended_auction_uuids: bool,
     started_epoch: &mut i64,
 ) -> bool {
-    match get_ended_auctions().await {
+    match get_ended_auctions(config, record_dir).await {
         Some(page_request) => {
             *started_epoch = page_request.last_updated;
+            let parse_started = Instant::now();
+            let mut parsed = 0;
 
             for mut auction in page_request.auctions {
                 if update_ended_auction_uuids {
-                    ended_auction_uuids.insert(auction.auction_id);
+                    ended_auction_uuids.insert(auction.auction_id.to_string());
                 }
 
-                // Always update if pets is enabled, otherwise check if only auction or bin are enabled
-                if !update_pets && !(update_average_auction && update_average_bin) {
+                // Always update if pets or sales are enabled, otherwise check if only auction or bin are enabled
+                if !update_pets && !update_sales && (!update_average_auction || !update_average_bin)
+                {
                     // Only update avg ah is enabled but is bin or only update avg bin is enabled but isn't bin
                     if (update_average_auction && auction.bin)
                         || (update_average_bin && !auction.bin)
@@ -565,32 +630,66 @@ async fn parse_ended_auctions(
                     }
                 }
 
-                let nbt = &parse_nbt(&auction.item_bytes).unwrap().i[0];
+                let nbt = match parse_item(&auction.item_bytes) {
+                    Ok(nbt) => nbt,
+                    Err(e) => {
+                        inc("query_api_nbt_errors_total", &[], 1.0);
+                        ingest_errors.insert(auction.auction_id, e);
+                        continue;
+                    }
+                };
                 let extra_attrs = &nbt.tag.extra_attributes;
-                let mut id = extra_attrs.id.to_owned();
 
-                if id == "PET" {
-                    let pet_info =
-                        serde_json::from_str::<PetInfo>(extra_attrs.pet.as_ref().unwrap()).unwrap();
-
-                    let item_name = MC_CODE_REGEX
-                        .replace_all(&nbt.tag.display.name, "")
-                        .to_string();
+                let mut pet_info = None;
+                let item_name = MC_CODE_REGEX
+                    .replace_all(&nbt.tag.display.name, "")
+                    .to_string();
+
+                if extra_attrs.id == "PET" {
+                    let pet_info_parsed = match parse_pet_info(extra_attrs) {
+                        Ok(pet_info_parsed) => pet_info_parsed,
+                        Err(e) => {
+                            ingest_errors.insert(auction.auction_id, e);
+                            continue;
+                        }
+                    };
+
+                    // Display names look like [Lvl 100] Wither Skeleton
+                    let Some((level, name)) = item_name
+                        .strip_prefix("[Lvl ")
+                        .and_then(|e| e.split_once("] "))
+                        .and_then(|(level, name)| Some((level.parse::<i16>().ok()?, name)))
+                    else {
+                        ingest_errors.insert(
+                            auction.auction_id,
+                            format!("Unable to parse pet name from {}", item_name),
+                        );
+                        continue;
+                    };
 
                     if update_pets {
+                        let tier_boost =
+                            pet_info_parsed.held_item.as_deref() == Some("PET_ITEM_TIER_BOOST");
+
+                        pet_sales.lock().unwrap().push(PetSalesDatabaseItem {
+                            uuid: auction.auction_id.to_owned(),
+                            end_t: auction.timestamp,
+                            name: name.replace(' ', "_").replace("_✦", "").to_uppercase(),
+                            level,
+                            tier: pet_info_parsed.tier.to_owned(),
+                            tier_boost,
+                            held_item: pet_info_parsed.held_item.to_owned(),
+                            skin: pet_info_parsed.skin.to_owned(),
+                            candy_used: pet_info_parsed.candy_used.unwrap_or(0),
+                            price: auction.price,
+                            bin: auction.bin,
+                        });
+
                         let pet_id = format!(
                             "{}_{}{}",
                             item_name.replace(' ', "_").replace("_✦", ""),
-                            pet_info.tier,
-                            if let Some(held_item) = pet_info.held_item {
-                                if held_item == "PET_ITEM_TIER_BOOST" {
-                                    "_TB"
-                                } else {
-                                    ""
-                                }
-                            } else {
-                                ""
-                            }
+                            pet_info_parsed.tier,
+                            if tier_boost { "_TB" } else { "" }
                         )
                         .to_uppercase();
 
@@ -607,109 +706,91 @@ async fn parse_ended_auctions(
                         }
                     }
 
-                    let mut split = item_name.split("] ");
-                    split.next();
-
-                    id = format!(
-                        "{};{}",
-                        split
-                            .next()
-                            .unwrap()
-                            .replace(' ', "_")
-                            .replace("_✦", "")
-                            .to_uppercase(),
-                        match pet_info.tier.as_str() {
-                            "COMMON" => 0,
-                            "UNCOMMON" => 1,
-                            "RARE" => 2,
-                            "EPIC" => 3,
-                            "LEGENDARY" => 4,
-                            "MYTHIC" => 5,
-                            _ => -1,
-                        }
-                    );
+                    pet_info = Some(pet_info_parsed);
                 }
 
-                if !update_average_bin && !update_average_auction {
+                if !update_average_bin && !update_average_auction && !update_sales {
                     continue;
                 }
 
-                if let Some(attributes) = &extra_attrs.attributes {
-                    if id == "ATTRIBUTE_SHARD" {
-                        if attributes.len() == 1 {
-                            for entry in attributes {
-                                id = format!("ATTRIBUTE_SHARD_{}", entry.0.to_uppercase());
-                                auction.price /= 2_i64.pow((entry.1 - 1) as u32);
-                            }
-                        }
-                    } else if !attributes.is_empty() {
-                        // Track average of item (regardless of attributes)
-                        if update_average_bin && auction.bin {
-                            update_average_map(avg_bin_prices, &id, auction.price, nbt.count);
-                        } else if update_average_auction && !auction.bin {
-                            update_average_map(avg_ah_prices, &id, auction.price, nbt.count);
-                        }
-
-                        for entry in attributes {
-                            id.push_str("+ATTRIBUTE_SHARD_");
-                            id.push_str(&entry.0.to_uppercase());
-                        }
+                let internal_id = match InternalId::new(&ItemInfo {
+                    extra_attrs,
+                    pet_info: pet_info.as_ref(),
+                    item_name: &item_name,
+                }) {
+                    Ok(internal_id) => internal_id,
+                    Err(e) => {
+                        ingest_errors.insert(auction.auction_id, e);
+                        continue;
                     }
+                };
+                let id = internal_id.id;
+
+                if update_sales {
+                    sale_prices.lock().unwrap().push(SalesDatabaseItem {
+                        uuid: auction.auction_id.to_owned(),
+                        end_t: auction.timestamp,
+                        item_name: item_name.to_owned(),
+                        item_id: extra_attrs.id.to_owned(),
+                        internal_id: id.to_owned(),
+                        price: auction.price,
+                        count: nbt.count,
+                        bin: auction.bin,
+                        enchants: extra_attrs.get_enchants(),
+                        attributes: extra_attrs.get_attributes(),
+                        potato_books: extra_attrs.hot_potato_count,
+                        stars: extra_attrs.get_stars(),
+                        farming_for_dummies: extra_attrs.farming_for_dummies_count,
+                        transmission_tuner: extra_attrs.tuned_transmission,
+                        mana_disintegrator: extra_attrs.mana_disintegrator_count,
+                        reforge: extra_attrs.modifier.to_owned(),
+                        rune: extra_attrs.get_rune(),
+                        skin: extra_attrs.skin.to_owned(),
+                        power_scroll: extra_attrs.power_ability_scroll.to_owned(),
+                        drill_upgrade_module: extra_attrs.drill_part_upgrade_module.to_owned(),
+                        drill_fuel_tank: extra_attrs.drill_part_fuel_tank.to_owned(),
+                        drill_engine: extra_attrs.drill_part_engine.to_owned(),
+                        dye: extra_attrs.dye_item.to_owned(),
+                        accessory_enrichment: extra_attrs.get_talisman_enrichment(),
+                        recombobulated: extra_attrs.is_recombobulated(),
+                        wood_singularity: extra_attrs.is_wood_singularity_applied(),
+                        art_of_war: extra_attrs.is_art_of_war_applied(),
+                        art_of_peace: extra_attrs.is_art_of_peace_applied(),
+                        etherwarp: extra_attrs.is_etherwarp_applied(),
+                        necron_scrolls: extra_attrs.ability_scroll.to_owned(),
+                        gemstones: extra_attrs.get_gemstones(),
+                    });
                 }
-                if id == "PARTY_HAT_CRAB" || id == "PARTY_HAT_CRAB_ANIMATED" {
-                    if let Some(party_hat_color) = &extra_attrs.party_hat_color {
-                        id = format!(
-                            "PARTY_HAT_CRAB_{}{}",
-                            party_hat_color.to_uppercase(),
-                            if id.ends_with("_ANIMATED") {
-                                "_ANIMATED"
-                            } else {
-                                ""
-                            }
-                        );
-                    }
-                } else if id == "PARTY_HAT_SLOTH" {
-                    if let Some(party_hat_emoji) = &extra_attrs.party_hat_emoji {
-                        id = format!("{}_{}", id, party_hat_emoji.to_uppercase());
-                    }
-                } else if id == "NEW_YEAR_CAKE" {
-                    if let Some(new_years_cake) = &extra_attrs.new_years_cake {
-                        id = format!("{}_{}", id, new_years_cake);
-                    }
-                } else if id == "MIDAS_SWORD" || id == "MIDAS_STAFF" {
-                    if let Some(winning_bid) = &extra_attrs.winning_bid {
-                        let best_bid = if id == "MIDAS_SWORD" {
-                            50000000
-                        } else {
-                            100000000
-                        };
-                        if winning_bid > &best_bid {
-                            id = format!("{}_{}", id, best_bid);
-                        }
-                    }
-                } else if id == "RUNE" {
-                    if let Some(runes) = &extra_attrs.runes {
-                        if runes.len() == 1 {
-                            for entry in runes {
-                                id = format!(
-                                    "{}_RUNE;{}",
-                                    entry.key().to_uppercase(),
-                                    entry.value()
-                                );
-                            }
-                        }
+
+                auction.price /= internal_id.price_divisor;
+
+                // Track average of item (regardless of attributes)
+                if let Some(base_id) = &internal_id.base_id {
+                    if update_average_bin && auction.bin {
+                        update_average_map(avg_bin_prices, base_id, auction.price, nbt.count);
+                    } else if update_average_auction && !auction.bin {
+                        update_average_map(avg_ah_prices, base_id, auction.price, nbt.count);
                     }
                 }
-                if extra_attrs.is_shiny() {
-                    id.push_str("_SHINY");
-                }
 
                 if update_average_bin && auction.bin {
                     update_average_map(avg_bin_prices, &id, auction.price, nbt.count);
                 } else if update_average_auction && !auction.bin {
                     update_average_map(avg_ah_prices, &id, auction.price, nbt.count);
                 }
+                parsed += 1;
             }
+
+            observe(
+                "query_api_page_seconds",
+                &[("endpoint", "auctions_ended"), ("stage", "parse")],
+                parse_started.elapsed().as_secs_f64(),
+            );
+            inc(
+                "query_api_auctions_parsed_total",
+                &[("kind", "ended")],
+                parsed as f64,
+            );
         }
         None => {
             error(String::from("Failed to fetch ended auctions"));
@@ -719,30 +800,113 @@ async fn parse_ended_auctions(
     true
 }
 
-/* Gets an auction page from the Hypixel API */
-async fn get_auction_page(page_number: i32) -> Option<Auctions> {
-    let res = HTTP_CLIENT
-        .get(format!(
-            "https://api.hypixel.net/skyblock/auctions?page={page_number}"
-        ))
-        .send()
-        .await;
-    if res.is_ok() {
-        res.unwrap().json().await.ok()
-    } else {
-        None
+/* Parses the NBT of an auction's item */
+fn parse_item(item_bytes: &str) -> Result<PartialNbtElement, String> {
+    parse_nbt(item_bytes)?
+        .i
+        .into_iter()
+        .next()
+        .ok_or_else(|| String::from("Item NBT does not contain any items"))
+}
+
+/* Parses the pet info of a pet's NBT */
+fn parse_pet_info(extra_attrs: &PartialExtraAttr) -> Result<PetInfo, String> {
+    match &extra_attrs.pet {
+        Some(pet) => serde_json::from_str::<PetInfo>(pet)
+            .map_err(|e| format!("Unable to parse pet info: {}", e)),
+        None => Err(String::from("Pet is missing its pet info")),
     }
 }
 
+/* Gets an auction page from the Hypixel API */
+async fn get_auction_page(
+    config: &Config,
+    record_dir: Option<&str>,
+    page_number: i32,
+) -> Option<Auctions> {
+    get_page(
+        config,
+        record_dir,
+        &format!("/skyblock/auctions?page={page_number}"),
+        &format!("auctions_{page_number}.json"),
+    )
+    .await
+}
+
 /* Gets ended auctions from the Hypixel API */
-async fn get_ended_auctions() -> Option<EndedAuctions> {
-    let res = HTTP_CLIENT
-        .get("https://api.hypixel.net/skyblock/auctions_ended")
-        .send()
-        .await;
-    if res.is_ok() {
-        res.unwrap().json().await.ok()
+async fn get_ended_auctions(config: &Config, record_dir: Option<&str>) -> Option<EndedAuctions> {
+    get_page(
+        config,
+        record_dir,
+        "/skyblock/auctions_ended",
+        "auctions_ended.json",
+    )
+    .await
+}
+
+/* Gets a page from the Hypixel API and counts whether it could be fetched */
+async fn get_page<T: DeserializeOwned>(
+    config: &Config,
+    record_dir: Option<&str>,
+    path: &str,
+    file_name: &str,
+) -> Option<T> {
+    let started = Instant::now();
+    let page = fetch_page(config, record_dir, path, file_name).await;
+
+    // Paths look like /skyblock/auctions?page=0
+    let endpoint = path
+        .trim_start_matches("/skyblock/")
+        .split('?')
+        .next()
+        .unwrap_or_default();
+    observe(
+        "query_api_page_seconds",
+        &[("endpoint", endpoint), ("stage", "fetch")],
+        started.elapsed().as_secs_f64(),
+    );
+    inc(
+        if page.is_some() {
+            "query_api_pages_fetched_total"
+        } else {
+            "query_api_pages_failed_total"
+        },
+        &[("endpoint", endpoint)],
+        1.0,
+    );
+
+    page
+}
+
+/* Gets a page from the Hypixel API (or from the replay directory when replaying) and records it if enabled */
+async fn fetch_page<T: DeserializeOwned>(
+    config: &Config,
+    record_dir: Option<&str>,
+    path: &str,
+    file_name: &str,
+) -> Option<T> {
+    let body = if config.replay_dir.is_empty() {
+        let body = match HTTP_CLIENT
+            .get(format!("{}{}", config.hypixel_api_base, path))
+            .send()
+            .await
+        {
+            Ok(res) => res.text().await.ok()?,
+            Err(_) => return None,
+        };
+
+        if let Some(record_dir) = record_dir {
+            if let Err(e) = fs::write(format!("{}/{}", record_dir, file_name), &body).await {
+                error(format!("Failed to record {}: {}", file_name, e));
+            }
+        }
+
+        body
     } else {
-        None
-    }
+        fs::read_to_string(format!("{}/{}", config.replay_dir, file_name))
+            .await
+            .ok()?
+    };
+
+    serde_json::from_str(&body).ok()
 }
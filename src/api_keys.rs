@@ -0,0 +1,160 @@
+/*
+ * Rust Query API - A versatile API facade for the Hypixel Auction API
+ * Copyright (c) 2022 kr45732
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::{
+    config::Config,
+    statics::API_KEYS,
+    structs::ApiKey,
+    utils::{get_client, get_timestamp_millis},
+};
+use base64::{engine::general_purpose, Engine};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+
+/// Seconds in a day, used for the time until daily quotas reset
+const SECONDS_PER_DAY: u64 = 86400;
+
+/// What the key of a request can access, which is checked once before routing
+#[derive(Clone)]
+pub struct Auth {
+    /// If the key can use endpoints that require a key
+    pub valid: bool,
+    /// If the key can use admin endpoints and parameters
+    pub admin: bool,
+    /// Name of the key in the audit log
+    pub name: String,
+    /// Key from the api_keys table, which is limited to its endpoints and daily quota
+    pub api_key: Option<ApiKey>,
+}
+
+impl Auth {
+    pub fn from_key(config: &Config, key: &str) -> Self {
+        // Keys from the environment are not named, so the start of the key is used
+        let name = format!("{}...", key.chars().take(4).collect::<String>());
+
+        if config.admin_api_key == key {
+            return Self {
+                valid: true,
+                admin: true,
+                name,
+                api_key: None,
+            };
+        }
+
+        if let Some(api_key) = find_api_key(key) {
+            let valid = !api_key.is_expired(get_timestamp_millis() as i64);
+            return Self {
+                valid,
+                admin: valid && api_key.admin,
+                name: api_key.name.clone(),
+                api_key: Some(api_key),
+            };
+        }
+
+        Self {
+            valid: config.api_key.is_empty() || key == config.api_key,
+            admin: false,
+            name,
+            api_key: None,
+        }
+    }
+}
+
+/* Hex encoded SHA-256 hash of a key, which is what the api_keys table stores */
+pub fn hash_key(key: &str) -> String {
+    Sha256::digest(key.as_bytes())
+        .iter()
+        .map(|e| format!("{:02x}", e))
+        .collect()
+}
+
+/* Creates a random key with 256 bits of entropy */
+pub fn generate_key() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/* Gets the key from the api_keys table matching the key of a request */
+pub fn find_api_key(key: &str) -> Option<ApiKey> {
+    if key.is_empty() {
+        return None;
+    }
+    API_KEYS.get(&hash_key(key)).map(|e| e.clone())
+}
+
+/* Reloads the keys from the api_keys table, removing keys that were deleted */
+pub async fn refresh_api_keys() -> Result<(), tokio_postgres::Error> {
+    let rows = get_client()
+        .await
+        .query("SELECT * FROM api_keys", &[])
+        .await?;
+
+    let mut key_hashes = HashSet::new();
+    for row in rows {
+        let api_key = ApiKey::from(row);
+        key_hashes.insert(api_key.key_hash.clone());
+        API_KEYS.insert(api_key.key_hash.clone(), api_key);
+    }
+    API_KEYS.retain(|key_hash, _| key_hashes.contains(key_hash));
+
+    Ok(())
+}
+
+/* Counts a request of the key to the endpoint, or returns false without counting it if the key has reached its daily quota */
+pub async fn record_usage(api_key: &ApiKey, endpoint: &str) -> Result<bool, tokio_postgres::Error> {
+    let mut database = get_client().await;
+
+    let Some(daily_quota) = api_key.daily_quota else {
+        database
+            .execute(
+                "INSERT INTO api_key_usage (name, day, endpoint, requests) VALUES ($1, (now() AT TIME ZONE 'UTC')::date, $2, 1)
+                    ON CONFLICT (name, day, endpoint) DO UPDATE SET requests = api_key_usage.requests + 1",
+                &[&api_key.name, &endpoint],
+            )
+            .await?;
+        return Ok(true);
+    };
+
+    // The quota covers every endpoint, so requests of the key wait for each other until the count is committed
+    let transaction = database.transaction().await?;
+    transaction
+        .execute(
+            "SELECT pg_advisory_xact_lock(hashtext($1))",
+            &[&api_key.name],
+        )
+        .await?;
+    let counted = transaction
+        .execute(
+            "INSERT INTO api_key_usage (name, day, endpoint, requests)
+                SELECT $1, (now() AT TIME ZONE 'UTC')::date, $2, 1
+                WHERE (SELECT COALESCE(SUM(requests), 0)::BIGINT FROM api_key_usage WHERE name = $1 AND day = (now() AT TIME ZONE 'UTC')::date) < $3
+                ON CONFLICT (name, day, endpoint) DO UPDATE SET requests = api_key_usage.requests + 1",
+            &[&api_key.name, &endpoint, &daily_quota],
+        )
+        .await?;
+    transaction.commit().await?;
+
+    Ok(counted > 0)
+}
+
+/* Seconds until daily quotas reset at midnight UTC */
+pub fn seconds_until_quota_reset(now_millis: u128) -> u64 {
+    SECONDS_PER_DAY - (now_millis / 1000) as u64 % SECONDS_PER_DAY
+}
@@ -0,0 +1,122 @@
+/*
+ * Rust Query API - A versatile API facade for the Hypixel Auction API
+ * Copyright (c) 2022 kr45732
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use async_compression::{
+    tokio::bufread::{BrotliEncoder, GzipEncoder, ZstdEncoder},
+    Level,
+};
+use futures::executor::block_on;
+use hyper::body::Bytes;
+use std::{io::Cursor, pin::Pin};
+use tokio::{
+    io::{AsyncBufRead, AsyncRead, AsyncReadExt},
+    task,
+};
+
+/// Bodies smaller than this are not worth compressing
+pub const MIN_COMPRESS_SIZE: u64 = 1024;
+
+/// Content codings responses can be compressed with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    Gzip,
+    Brotli,
+    Zstd,
+}
+
+impl Encoding {
+    /* Name used in the Accept-Encoding and Content-Encoding headers */
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Brotli => "br",
+            Self::Zstd => "zstd",
+        }
+    }
+
+    /* Gets the supported encoding with the highest quality in the Accept-Encoding header, preferring zstd, then br, then gzip on ties, or None if the body should not be compressed */
+    pub fn negotiate(accept_encoding: Option<&str>) -> Option<Self> {
+        let mut qualities = Vec::new();
+        let mut wildcard = None;
+        for coding in accept_encoding.unwrap_or_default().split(',') {
+            let mut parts = coding.split(';').map(|e| e.trim());
+            let name = parts.next().unwrap_or_default().to_lowercase();
+            let quality = parts
+                .find_map(|e| e.strip_prefix("q="))
+                .and_then(|e| e.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if name == "*" {
+                wildcard = Some(quality);
+            } else {
+                qualities.push((name, quality));
+            }
+        }
+
+        let mut best: Option<(Self, f32)> = None;
+        for encoding in [Self::Zstd, Self::Brotli, Self::Gzip] {
+            // Codings that are not listed get the quality of the wildcard
+            let quality = qualities
+                .iter()
+                .find(|(name, _)| name == encoding.name())
+                .map(|(_, quality)| *quality)
+                .or(wildcard)
+                .unwrap_or(0.0);
+            if quality > 0.0 && best.map_or(true, |(_, best_quality)| quality > best_quality) {
+                best = Some((encoding, quality));
+            }
+        }
+        best.map(|(encoding, _)| encoding)
+    }
+
+    /* Wraps a reader so it reads the compressed data. Brotli is below its max level (11), which takes seconds for large snapshots, and lower still for responses that are compressed on every request */
+    pub fn encoder<R>(&self, reader: R, cached: bool) -> Pin<Box<dyn AsyncRead + Send + Sync>>
+    where
+        R: AsyncBufRead + Send + Sync + 'static,
+    {
+        match self {
+            Self::Gzip => Box::pin(GzipEncoder::with_quality(reader, Level::Default)),
+            Self::Brotli => Box::pin(BrotliEncoder::with_quality(
+                reader,
+                if cached {
+                    Level::Precise(9)
+                } else {
+                    Level::Precise(4)
+                },
+            )),
+            Self::Zstd => Box::pin(ZstdEncoder::with_quality(reader, Level::Default)),
+        }
+    }
+
+    /* Compresses a whole body at the level for cached responses, on a blocking thread since large bodies take a while */
+    pub async fn compress(&self, data: Bytes) -> std::io::Result<Bytes> {
+        let encoding = *self;
+        task::spawn_blocking(move || {
+            // The reader is in memory, so the encoder never waits on anything
+            block_on(async {
+                let mut compressed = Vec::new();
+                encoding
+                    .encoder(Cursor::new(data), true)
+                    .read_to_end(&mut compressed)
+                    .await?;
+                Ok(compressed.into())
+            })
+        })
+        .await
+        .map_err(std::io::Error::other)?
+    }
+}
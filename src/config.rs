@@ -16,7 +16,11 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::{collections::HashSet, env, str::FromStr};
+use crate::storage::StorageBackend;
+use std::{collections::HashSet, env, net::IpAddr, str::FromStr};
+
+/// Max connections of the database pool
+pub const DATABASE_POOL_SIZE: usize = 16;
 
 #[derive(Debug, PartialEq, Eq, Hash)]
 pub enum Feature {
@@ -26,6 +30,8 @@ pub enum Feature {
     Underbin,
     AverageAuction,
     AverageBin,
+    Sales,
+    LowestbinHistory,
 }
 
 impl FromStr for Feature {
@@ -39,6 +45,8 @@ impl FromStr for Feature {
             "UNDERBIN" => Self::Underbin,
             "AVERAGE_AUCTION" => Self::AverageAuction,
             "AVERAGE_BIN" => Self::AverageBin,
+            "SALES" => Self::Sales,
+            "LOWESTBIN_HISTORY" => Self::LowestbinHistory,
             _ => return Err(format!("Unknown feature flag {}", s)),
         })
     }
@@ -55,6 +63,31 @@ pub struct Config {
     pub admin_api_key: String,
     pub debug: bool,
     pub disable_updating: bool,
+    pub hypixel_api_base: String,
+    pub record_dir: String,
+    pub replay_dir: String,
+    pub sales_retention: i64,
+    pub lowestbin_history_retention: i64,
+    pub pets_retention: i64,
+    pub storage: StorageBackend,
+    pub query_timeout: i64,
+    pub raw_sql_query: bool,
+    /// Role admin and POST queries switch to, where empty keeps the role of POSTGRES_URL
+    pub query_database_role: String,
+    /// Origins browsers can call the API from, where * allows every origin and no origins disables CORS
+    pub cors_allowed_origins: Vec<String>,
+    /// Request headers browsers can send from an allowed origin
+    pub cors_allowed_headers: String,
+    /// Seconds browsers can cache a preflight response for
+    pub cors_max_age: u64,
+    /// Requests per second each client can make, where 0 disables rate limiting
+    pub rate_limit: f64,
+    /// Requests a client can make at once before being limited to the rate limit
+    pub rate_limit_burst: f64,
+    /// IPs of proxies whose X-Forwarded-For header is used for the IP of the client, where * trusts every proxy
+    pub trusted_proxies: Vec<String>,
+    /// Max requests using database connections at once, below the pool size so updates always have connections
+    pub database_request_limit: usize,
     // Shh, don't tell anyone!
     pub super_secret_config_option: bool,
 }
@@ -82,6 +115,75 @@ impl Config {
             .unwrap_or_else(|_| String::from("false"))
             .parse()
             .unwrap_or(false);
+        let hypixel_api_base = env::var("HYPIXEL_API_BASE")
+            .unwrap_or_else(|_| String::from("https://api.hypixel.net"))
+            .trim_end_matches('/')
+            .to_string();
+        let record_dir = env::var("RECORD_DIR").unwrap_or_default();
+        let replay_dir = env::var("REPLAY_DIR").unwrap_or_default();
+        let sales_retention = env::var("SALES_RETENTION")
+            .unwrap_or_else(|_| String::from("7"))
+            .parse::<i64>()
+            .expect("SALES_RETENTION not valid");
+        let lowestbin_history_retention = env::var("LOWESTBIN_HISTORY_RETENTION")
+            .unwrap_or_else(|_| String::from("7"))
+            .parse::<i64>()
+            .expect("LOWESTBIN_HISTORY_RETENTION not valid");
+        let pets_retention = env::var("PETS_RETENTION")
+            .unwrap_or_else(|_| String::from("30"))
+            .parse::<i64>()
+            .expect("PETS_RETENTION not valid");
+        let storage = env::var("STORAGE")
+            .unwrap_or_else(|_| String::from("MEMORY"))
+            .parse::<StorageBackend>()
+            .expect("STORAGE not valid");
+        let query_timeout = env::var("QUERY_TIMEOUT")
+            .unwrap_or_else(|_| String::from("5000"))
+            .parse::<i64>()
+            .expect("QUERY_TIMEOUT not valid");
+        let raw_sql_query = env::var("RAW_SQL_QUERY")
+            .unwrap_or_else(|_| String::from("false"))
+            .parse()
+            .unwrap_or(false);
+        let query_database_role = env::var("QUERY_DATABASE_ROLE").unwrap_or_default();
+        let cors_allowed_origins = env::var("CORS_ALLOWED_ORIGINS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|e| e.trim().trim_end_matches('/').to_string())
+            .filter(|e| !e.is_empty())
+            .collect::<Vec<String>>();
+        let cors_allowed_headers = env::var("CORS_ALLOWED_HEADERS")
+            .unwrap_or_else(|_| String::from("Authorization, Content-Type, X-API-Key"));
+        let cors_max_age = env::var("CORS_MAX_AGE")
+            .unwrap_or_else(|_| String::from("86400"))
+            .parse::<u64>()
+            .expect("CORS_MAX_AGE not valid");
+        let rate_limit = env::var("RATE_LIMIT")
+            .unwrap_or_else(|_| String::from("0"))
+            .parse::<f64>()
+            .expect("RATE_LIMIT not valid");
+        let rate_limit_burst = env::var("RATE_LIMIT_BURST")
+            .map(|e| e.parse::<f64>().expect("RATE_LIMIT_BURST not valid"))
+            .unwrap_or((rate_limit * 2.0).max(1.0));
+        if rate_limit_burst < 1.0 {
+            panic!("RATE_LIMIT_BURST must be at least 1");
+        }
+        let trusted_proxies = env::var("TRUSTED_PROXIES")
+            .unwrap_or_default()
+            .split(',')
+            .map(|e| e.trim().to_string())
+            .filter(|e| !e.is_empty())
+            .collect::<Vec<String>>();
+        let database_request_limit = env::var("DATABASE_REQUEST_LIMIT")
+            .unwrap_or_else(|_| String::from("12"))
+            .parse::<usize>()
+            .expect("DATABASE_REQUEST_LIMIT not valid");
+        if database_request_limit == 0 || database_request_limit >= DATABASE_POOL_SIZE {
+            panic!(
+                "DATABASE_REQUEST_LIMIT must be between 1 and {}, so auction updates always have connections",
+                DATABASE_POOL_SIZE - 1
+            );
+        }
         let postgres_url = get_env("POSTGRES_URL");
         let features = get_env("FEATURES")
             .replace(',', "+")
@@ -94,6 +196,10 @@ impl Config {
         if features.contains(&Feature::Underbin) && !features.contains(&Feature::Lowestbin) {
             panic!("The LOWESTBIN feature must be enabled to enable the UNDERBIN feature");
         }
+        if features.contains(&Feature::LowestbinHistory) && !features.contains(&Feature::Lowestbin)
+        {
+            panic!("The LOWESTBIN feature must be enabled to enable the LOWESTBIN_HISTORY feature");
+        }
         Config {
             enabled_features: features,
             full_url: format!("{}:{}", base_url, port),
@@ -105,6 +211,23 @@ impl Config {
             port,
             debug,
             disable_updating,
+            hypixel_api_base,
+            record_dir,
+            replay_dir,
+            sales_retention,
+            lowestbin_history_retention,
+            pets_retention,
+            storage,
+            query_timeout,
+            raw_sql_query,
+            query_database_role,
+            cors_allowed_origins,
+            cors_allowed_headers,
+            cors_max_age,
+            rate_limit,
+            rate_limit_burst,
+            trusted_proxies,
+            database_request_limit,
             super_secret_config_option,
         }
     }
@@ -112,4 +235,21 @@ impl Config {
     pub fn is_enabled(&self, feature: Feature) -> bool {
         self.enabled_features.contains(&feature)
     }
+
+    pub fn trusts_proxy(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies
+            .iter()
+            .any(|e| e == "*" || e.parse::<IpAddr>().is_ok_and(|e| e == ip))
+    }
+
+    /* Gets the Access-Control-Allow-Origin value for a request from the origin, or None if the origin is not allowed */
+    pub fn cors_origin<'a>(&'a self, origin: &'a str) -> Option<&'a str> {
+        if self.cors_allowed_origins.iter().any(|e| e == "*") {
+            Some("*")
+        } else if self.cors_allowed_origins.iter().any(|e| e == origin) {
+            Some(origin)
+        } else {
+            None
+        }
+    }
 }
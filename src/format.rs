@@ -0,0 +1,204 @@
+/*
+ * Rust Query API - A versatile API facade for the Hypixel Auction API
+ * Copyright (c) 2022 kr45732
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use hyper::body::Bytes;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// How a list of rows is serialized
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    /// Comma separated values with a header row
+    Csv,
+    /// One JSON object per line
+    Ndjson,
+}
+
+impl Format {
+    /* Gets the format from the format parameter, else the preferred supported type of the Accept header, else JSON */
+    pub fn negotiate(format: &str, accept: Option<&str>) -> Result<Self, String> {
+        if !format.is_empty() {
+            return match format {
+                "json" => Ok(Self::Json),
+                "csv" => Ok(Self::Csv),
+                "ndjson" => Ok(Self::Ndjson),
+                _ => Err(format!("Unknown format {}", format)),
+            };
+        }
+
+        // Supported type with the highest quality, where earlier types win ties
+        let mut best: Option<(Self, f32)> = None;
+        for media_range in accept.unwrap_or_default().split(',') {
+            let mut parts = media_range.split(';').map(|e| e.trim());
+            let format = match parts.next().unwrap_or_default() {
+                "application/json" => Self::Json,
+                "text/csv" => Self::Csv,
+                "application/x-ndjson" | "application/ndjson" => Self::Ndjson,
+                _ => continue,
+            };
+            let quality = parts
+                .find_map(|e| e.strip_prefix("q="))
+                .and_then(|e| e.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if quality > 0.0 && best.map_or(true, |(_, best_quality)| quality > best_quality) {
+                best = Some((format, quality));
+            }
+        }
+        Ok(best.map_or(Self::Json, |(format, _)| format))
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    /// Appended to ETags so each format of a snapshot is cached separately
+    pub fn etag_suffix(&self) -> &'static str {
+        match self {
+            Self::Json => "",
+            Self::Csv => "-csv",
+            Self::Ndjson => "-ndjson",
+        }
+    }
+}
+
+/// Writes rows one at a time so they can be streamed
+pub struct RowWriter {
+    format: Format,
+    /// CSV columns, where other fields of a row are ignored
+    columns: Vec<String>,
+    rows: usize,
+}
+
+impl RowWriter {
+    pub fn new(format: Format, columns: &[&str]) -> Self {
+        Self {
+            format,
+            columns: columns.iter().map(|e| e.to_string()).collect(),
+            rows: 0,
+        }
+    }
+
+    /* Start of the body (the opening bracket of a JSON array or the CSV header) */
+    pub fn start(&self) -> Bytes {
+        match self.format {
+            Format::Json => Bytes::from_static(b"["),
+            Format::Csv => {
+                let mut header = self
+                    .columns
+                    .iter()
+                    .map(|e| csv_escape(e))
+                    .collect::<Vec<String>>()
+                    .join(",");
+                header.push('\n');
+                header.into()
+            }
+            Format::Ndjson => Bytes::new(),
+        }
+    }
+
+    pub fn row<T: ?Sized + Serialize>(&mut self, row: &T) -> Bytes {
+        self.rows += 1;
+        match self.format {
+            Format::Json => {
+                let mut bytes = if self.rows == 1 {
+                    Vec::new()
+                } else {
+                    vec![b',']
+                };
+                serde_json::to_writer(&mut bytes, row).unwrap();
+                bytes.into()
+            }
+            Format::Csv => {
+                let row = serde_json::to_value(row).unwrap();
+                let mut line = self
+                    .columns
+                    .iter()
+                    .map(|e| match row.get(e) {
+                        None | Some(Value::Null) => String::new(),
+                        Some(Value::String(value)) => csv_escape(value),
+                        Some(value) => csv_escape(&value.to_string()),
+                    })
+                    .collect::<Vec<String>>()
+                    .join(",");
+                line.push('\n');
+                line.into()
+            }
+            Format::Ndjson => {
+                let mut bytes = serde_json::to_vec(row).unwrap();
+                bytes.push(b'\n');
+                bytes.into()
+            }
+        }
+    }
+
+    /* End of the body (the closing bracket of a JSON array) */
+    pub fn end(&self) -> Bytes {
+        match self.format {
+            Format::Json => Bytes::from_static(b"]"),
+            Format::Csv | Format::Ndjson => Bytes::new(),
+        }
+    }
+
+    /* Writes every row into one body */
+    pub fn write_all<T: Serialize>(mut self, rows: impl IntoIterator<Item = T>) -> Bytes {
+        let mut body = self.start().to_vec();
+        for row in rows {
+            body.extend_from_slice(&self.row(&row));
+        }
+        body.extend_from_slice(&self.end());
+        body.into()
+    }
+}
+
+/* Converts a map into rows sorted by key, with the key in the key column and either the fields of each value or the value in the value column */
+pub fn map_rows<V: Serialize>(
+    map: impl IntoIterator<Item = (String, V)>,
+    key_column: &str,
+    value_column: &str,
+) -> Vec<Value> {
+    let mut rows = map
+        .into_iter()
+        .map(|(key, value)| {
+            let mut row = Map::new();
+            row.insert(key_column.to_string(), Value::String(key));
+            match serde_json::to_value(value).unwrap() {
+                Value::Object(fields) => row.extend(fields),
+                value => {
+                    row.insert(value_column.to_string(), value);
+                }
+            }
+            Value::Object(row)
+        })
+        .collect::<Vec<Value>>();
+    rows.sort_by(|a, b| a[key_column].as_str().cmp(&b[key_column].as_str()));
+    rows
+}
+
+/* Quotes a CSV field if it contains a comma, quote, or line break */
+fn csv_escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
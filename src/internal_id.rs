@@ -0,0 +1,355 @@
+/*
+ * Rust Query API - A versatile API facade for the Hypixel Auction API
+ * Copyright (c) 2022 kr45732
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::structs::{PartialExtraAttr, PetInfo};
+
+/// The parts of an item needed to build its internal id
+pub struct ItemInfo<'a> {
+    pub extra_attrs: &'a PartialExtraAttr,
+    pub pet_info: Option<&'a PetInfo>,
+    /// Item name without color codes (e.g. [Lvl 100] Wither Skeleton)
+    pub item_name: &'a str,
+}
+
+/// Id used to group the same kind of item for lowest bins, under bins, and averages
+pub struct InternalId {
+    pub id: String,
+    /// Id of the item regardless of its attributes (only set if the item has attributes)
+    pub base_id: Option<String>,
+    /// What the price should be divided by to get the price of one unit (e.g. a level 1 attribute shard)
+    pub price_divisor: i64,
+}
+
+/// A special case of the internal id
+pub struct IdRule {
+    pub name: &'static str,
+    /// Item ids (ExtraAttributes.id) the rule applies to. Empty applies to all items
+    pub ids: &'static [&'static str],
+    /// Changes the id built by the previous rules, or returns why the item could not be parsed
+    pub apply: fn(&mut InternalId, &ItemInfo) -> Result<(), String>,
+}
+
+/// Rules applied, in order, to build an internal id. New special cases should be added here
+pub const ID_RULES: &[IdRule] = &[
+    IdRule {
+        name: "pet",
+        ids: &["PET"],
+        apply: pet_rule,
+    },
+    IdRule {
+        name: "attributes",
+        ids: &[],
+        apply: attributes_rule,
+    },
+    IdRule {
+        name: "party_hat_crab",
+        ids: &["PARTY_HAT_CRAB", "PARTY_HAT_CRAB_ANIMATED"],
+        apply: party_hat_crab_rule,
+    },
+    IdRule {
+        name: "party_hat_sloth",
+        ids: &["PARTY_HAT_SLOTH"],
+        apply: party_hat_sloth_rule,
+    },
+    IdRule {
+        name: "new_year_cake",
+        ids: &["NEW_YEAR_CAKE"],
+        apply: new_year_cake_rule,
+    },
+    IdRule {
+        name: "midas",
+        ids: &["MIDAS_SWORD", "MIDAS_STAFF"],
+        apply: midas_rule,
+    },
+    IdRule {
+        name: "rune",
+        ids: &["RUNE"],
+        apply: rune_rule,
+    },
+    IdRule {
+        name: "shiny",
+        ids: &[],
+        apply: shiny_rule,
+    },
+];
+
+impl InternalId {
+    pub fn new(item: &ItemInfo) -> Result<Self, String> {
+        let mut internal_id = Self {
+            id: item.extra_attrs.id.to_owned(),
+            base_id: None,
+            price_divisor: 1,
+        };
+
+        for rule in ID_RULES {
+            if rule.ids.is_empty() || rule.ids.contains(&item.extra_attrs.id.as_str()) {
+                (rule.apply)(&mut internal_id, item)?;
+            }
+        }
+
+        Ok(internal_id)
+    }
+}
+
+pub fn pet_tier_index(tier: &str) -> i32 {
+    match tier {
+        "COMMON" => 0,
+        "UNCOMMON" => 1,
+        "RARE" => 2,
+        "EPIC" => 3,
+        "LEGENDARY" => 4,
+        "MYTHIC" => 5,
+        _ => -1,
+    }
+}
+
+/* NAME;TIER_INDEX (e.g. WITHER_SKELETON;4). If the pet is tier boosted, the tier is the rarity after boosting */
+fn pet_rule(internal_id: &mut InternalId, item: &ItemInfo) -> Result<(), String> {
+    if let Some(pet_info) = item.pet_info {
+        let mut split = item.item_name.split("] ");
+        split.next();
+
+        let Some(pet_name) = split.next() else {
+            return Err(format!("Unable to parse pet name from {}", item.item_name));
+        };
+        internal_id.id = format!(
+            "{};{}",
+            pet_name.replace(' ', "_").replace("_✦", "").to_uppercase(),
+            pet_tier_index(&pet_info.tier)
+        );
+    }
+    Ok(())
+}
+
+/* ATTRIBUTE_SHARD_NAME priced per level 1 shard, or ID+ATTRIBUTE_SHARD_NAME+... for items with attributes */
+fn attributes_rule(internal_id: &mut InternalId, item: &ItemInfo) -> Result<(), String> {
+    if let Some(attributes) = &item.extra_attrs.attributes {
+        if item.extra_attrs.id == "ATTRIBUTE_SHARD" {
+            if attributes.len() == 1 {
+                for entry in attributes {
+                    internal_id.id = format!("ATTRIBUTE_SHARD_{}", entry.0.to_uppercase());
+                    internal_id.price_divisor = 2_i64.pow((entry.1 - 1) as u32);
+                }
+            }
+        } else if !attributes.is_empty() {
+            internal_id.base_id = Some(internal_id.id.to_owned());
+            for entry in attributes {
+                internal_id.id.push_str("+ATTRIBUTE_SHARD_");
+                internal_id.id.push_str(&entry.0.to_uppercase());
+            }
+        }
+    }
+    Ok(())
+}
+
+/* PARTY_HAT_CRAB_COLOR or PARTY_HAT_CRAB_COLOR_ANIMATED */
+fn party_hat_crab_rule(internal_id: &mut InternalId, item: &ItemInfo) -> Result<(), String> {
+    if let Some(party_hat_color) = &item.extra_attrs.party_hat_color {
+        internal_id.id = format!(
+            "PARTY_HAT_CRAB_{}{}",
+            party_hat_color.to_uppercase(),
+            if item.extra_attrs.id.ends_with("_ANIMATED") {
+                "_ANIMATED"
+            } else {
+                ""
+            }
+        );
+    }
+    Ok(())
+}
+
+/* PARTY_HAT_SLOTH_EMOJI */
+fn party_hat_sloth_rule(internal_id: &mut InternalId, item: &ItemInfo) -> Result<(), String> {
+    if let Some(party_hat_emoji) = &item.extra_attrs.party_hat_emoji {
+        internal_id.id = format!("{}_{}", item.extra_attrs.id, party_hat_emoji.to_uppercase());
+    }
+    Ok(())
+}
+
+/* NEW_YEAR_CAKE_YEAR */
+fn new_year_cake_rule(internal_id: &mut InternalId, item: &ItemInfo) -> Result<(), String> {
+    if let Some(new_years_cake) = &item.extra_attrs.new_years_cake {
+        internal_id.id = format!("{}_{}", item.extra_attrs.id, new_years_cake);
+    }
+    Ok(())
+}
+
+/* MIDAS_SWORD_50000000 or MIDAS_STAFF_100000000 if the winning bid is above the max */
+fn midas_rule(internal_id: &mut InternalId, item: &ItemInfo) -> Result<(), String> {
+    if let Some(winning_bid) = &item.extra_attrs.winning_bid {
+        let best_bid = if item.extra_attrs.id == "MIDAS_SWORD" {
+            50000000
+        } else {
+            100000000
+        };
+        if winning_bid > &best_bid {
+            internal_id.id = format!("{}_{}", item.extra_attrs.id, best_bid);
+        }
+    }
+    Ok(())
+}
+
+/* NAME_RUNE;LEVEL */
+fn rune_rule(internal_id: &mut InternalId, item: &ItemInfo) -> Result<(), String> {
+    if let Some(runes) = &item.extra_attrs.runes {
+        if runes.len() == 1 {
+            for entry in runes {
+                internal_id.id = format!("{}_RUNE;{}", entry.key().to_uppercase(), entry.value());
+            }
+        }
+    }
+    Ok(())
+}
+
+/* ID_SHINY */
+fn shiny_rule(internal_id: &mut InternalId, item: &ItemInfo) -> Result<(), String> {
+    if item.extra_attrs.is_shiny() {
+        internal_id.id.push_str("_SHINY");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn build(extra_attrs: Value, item_name: &str) -> Result<InternalId, String> {
+        let extra_attrs: PartialExtraAttr = serde_json::from_value(extra_attrs).unwrap();
+        InternalId::new(&ItemInfo {
+            extra_attrs: &extra_attrs,
+            pet_info: None,
+            item_name,
+        })
+    }
+
+    fn build_pet(item_name: &str, tier: &str) -> Result<InternalId, String> {
+        let extra_attrs: PartialExtraAttr = serde_json::from_value(json!({ "id": "PET" })).unwrap();
+        let pet_info: PetInfo = serde_json::from_value(json!({ "tier": tier })).unwrap();
+        InternalId::new(&ItemInfo {
+            extra_attrs: &extra_attrs,
+            pet_info: Some(&pet_info),
+            item_name,
+        })
+    }
+
+    #[test]
+    fn pet() {
+        let internal_id = build_pet("[Lvl 100] Golden Dragon ✦", "LEGENDARY").unwrap();
+        assert_eq!(internal_id.id, "GOLDEN_DRAGON;4");
+    }
+
+    #[test]
+    fn pet_without_name() {
+        assert_eq!(
+            build_pet("Wither Skeleton", "LEGENDARY").err().unwrap(),
+            "Unable to parse pet name from Wither Skeleton"
+        );
+    }
+
+    #[test]
+    fn attribute_shard() {
+        let internal_id = build(
+            json!({ "id": "ATTRIBUTE_SHARD", "attributes": { "mana_pool": 3 } }),
+            "Attribute Shard",
+        )
+        .unwrap();
+        assert_eq!(internal_id.id, "ATTRIBUTE_SHARD_MANA_POOL");
+        assert_eq!(internal_id.price_divisor, 4);
+        assert_eq!(internal_id.base_id, None);
+    }
+
+    #[test]
+    fn attributes() {
+        let internal_id = build(
+            json!({ "id": "TERROR_BOOTS", "attributes": { "veteran": 2, "dominance": 1 } }),
+            "Terror Boots",
+        )
+        .unwrap();
+        assert_eq!(
+            internal_id.id,
+            "TERROR_BOOTS+ATTRIBUTE_SHARD_DOMINANCE+ATTRIBUTE_SHARD_VETERAN"
+        );
+        assert_eq!(internal_id.base_id.as_deref(), Some("TERROR_BOOTS"));
+        assert_eq!(internal_id.price_divisor, 1);
+    }
+
+    #[test]
+    fn party_hat_crab() {
+        let internal_id = build(
+            json!({ "id": "PARTY_HAT_CRAB_ANIMATED", "party_hat_color": "red" }),
+            "Crab Hat of Celebration",
+        )
+        .unwrap();
+        assert_eq!(internal_id.id, "PARTY_HAT_CRAB_RED_ANIMATED");
+    }
+
+    #[test]
+    fn party_hat_sloth() {
+        let internal_id = build(
+            json!({ "id": "PARTY_HAT_SLOTH", "party_hat_emoji": "cool" }),
+            "Sloth Hat of Celebration",
+        )
+        .unwrap();
+        assert_eq!(internal_id.id, "PARTY_HAT_SLOTH_COOL");
+    }
+
+    #[test]
+    fn new_year_cake() {
+        let internal_id = build(
+            json!({ "id": "NEW_YEAR_CAKE", "new_years_cake": 300 }),
+            "New Year Cake",
+        )
+        .unwrap();
+        assert_eq!(internal_id.id, "NEW_YEAR_CAKE_300");
+    }
+
+    #[test]
+    fn midas() {
+        let internal_id = build(
+            json!({ "id": "MIDAS_SWORD", "winning_bid": 60000000 }),
+            "Midas' Sword",
+        )
+        .unwrap();
+        assert_eq!(internal_id.id, "MIDAS_SWORD_50000000");
+
+        let internal_id = build(
+            json!({ "id": "MIDAS_STAFF", "winning_bid": 60000000 }),
+            "Midas Staff",
+        )
+        .unwrap();
+        assert_eq!(internal_id.id, "MIDAS_STAFF");
+    }
+
+    #[test]
+    fn rune() {
+        let internal_id = build(
+            json!({ "id": "RUNE", "runes": { "MUSIC": 3 } }),
+            "◆ Music Rune III",
+        )
+        .unwrap();
+        assert_eq!(internal_id.id, "MUSIC_RUNE;3");
+    }
+
+    #[test]
+    fn shiny() {
+        let internal_id =
+            build(json!({ "id": "HYPERION", "is_shiny": 1 }), "Shiny Hyperion").unwrap();
+        assert_eq!(internal_id.id, "HYPERION_SHINY");
+    }
+}
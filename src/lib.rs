@@ -19,9 +19,17 @@
 #![allow(clippy::too_many_arguments)]
 
 pub mod api_handler;
+pub mod api_keys;
+pub mod compression;
 pub mod config;
+pub mod format;
+pub mod internal_id;
+pub mod metrics;
+pub mod query_filter;
+pub mod rate_limit;
 pub mod server;
 pub mod statics;
+pub mod storage;
 pub mod structs;
 pub mod utils;
 pub mod webhook;
@@ -20,18 +20,17 @@ use deadpool_postgres::{Manager, ManagerConfig, Pool, RecyclingMethod, Runtime};
 use dotenv::dotenv;
 use query_api::{
     api_handler::update_auctions,
-    config::{Config, Feature},
+    api_keys::refresh_api_keys,
+    config::{Config, Feature, DATABASE_POOL_SIZE},
     server::start_server,
     statics::{BID_ARRAY, DATABASE, WEBHOOK},
-    utils::{info, start_auction_loop},
+    storage::StorageBackend,
+    utils::{error, info, start_auction_loop},
     webhook::Webhook,
 };
 use simplelog::{CombinedLogger, LevelFilter, SimpleLogger, WriteLogger};
-use std::{
-    error::Error,
-    fs::{self, File},
-    sync::Arc,
-};
+use std::{error::Error, fs::File, sync::Arc};
+use tokio::time::{self, Duration};
 use tokio_postgres::NoTls;
 
 /* Entry point to the program. Creates loggers, reads config, creates tables, starts auction loop and server */
@@ -74,6 +73,8 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
         || config.is_enabled(Feature::AverageAuction)
         || config.is_enabled(Feature::AverageBin)
         || config.is_enabled(Feature::Pets)
+        || config.is_enabled(Feature::Sales)
+        || config.storage == StorageBackend::Postgres
     {
         // Connect to database
         let database = DATABASE
@@ -87,13 +88,53 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
                         recycling_method: RecyclingMethod::Fast,
                     },
                 ))
-                .max_size(16)
+                .max_size(DATABASE_POOL_SIZE)
                 .runtime(Runtime::Tokio1)
                 .build()?,
             )
             .get()
             .await?;
 
+        // Create API keys table if doesn't exist
+        let _ = database
+            .simple_query(
+                "CREATE TABLE IF NOT EXISTS api_keys (
+                        name TEXT NOT NULL PRIMARY KEY,
+                        key_hash TEXT NOT NULL UNIQUE,
+                        admin BOOLEAN NOT NULL,
+                        endpoints TEXT[] NOT NULL,
+                        expires_at BIGINT,
+                        daily_quota BIGINT,
+                        created BIGINT NOT NULL
+                    )",
+            )
+            .await?;
+
+        // Create API key usage table if doesn't exist
+        let _ = database
+            .simple_query(
+                "CREATE TABLE IF NOT EXISTS api_key_usage (
+                        name TEXT NOT NULL,
+                        day DATE NOT NULL,
+                        endpoint TEXT NOT NULL,
+                        requests BIGINT NOT NULL,
+                        PRIMARY KEY (name, day, endpoint)
+                    )",
+            )
+            .await?;
+
+        // Reload API keys every minute so keys changed through other instances are used
+        refresh_api_keys().await?;
+        tokio::spawn(async {
+            let mut interval = time::interval(Duration::from_secs(60));
+            loop {
+                interval.tick().await;
+                if let Err(e) = refresh_api_keys().await {
+                    error(format!("Error when refreshing API keys: {}", e));
+                }
+            }
+        });
+
         if config.is_enabled(Feature::Query) {
             // Create bid custom type
             let _ = database
@@ -155,9 +196,24 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
                         )",
                 )
                 .await?;
+
+            // Create audit log table for admin queries if doesn't exist
+            let _ = database
+                .simple_query(
+                    "CREATE TABLE IF NOT EXISTS audit_log (
+                            time_t BIGINT,
+                            api_key TEXT,
+                            query TEXT,
+                            error TEXT
+                        )",
+                )
+                .await?;
         }
 
-        if config.is_enabled(Feature::AverageAuction) || config.is_enabled(Feature::AverageBin) {
+        if config.is_enabled(Feature::AverageAuction)
+            || config.is_enabled(Feature::AverageBin)
+            || config.is_enabled(Feature::Pets)
+        {
             // Create avg_ah custom type
             let _ = database
                 .simple_query(
@@ -221,40 +277,172 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
             }
         }
 
-        if config.is_enabled(Feature::Pets) {
-            // Create pets table if doesn't exist
+        if config.is_enabled(Feature::Sales) {
+            // Create ended auctions table if doesn't exist
+            let _ = database
+                .simple_query(
+                    "CREATE TABLE IF NOT EXISTS ended_auctions (
+                            uuid TEXT NOT NULL PRIMARY KEY,
+                            end_t BIGINT,
+                            item_name TEXT,
+                            item_id TEXT,
+                            internal_id TEXT,
+                            price BIGINT,
+                            count SMALLINT,
+                            bin BOOLEAN,
+                            enchants TEXT[],
+                            attributes TEXT[],
+                            potato_books SMALLINT,
+                            stars SMALLINT,
+                            farming_for_dummies SMALLINT,
+                            transmission_tuner SMALLINT,
+                            mana_disintegrator SMALLINT,
+                            reforge TEXT,
+                            rune TEXT,
+                            skin TEXT,
+                            power_scroll TEXT,
+                            drill_upgrade_module TEXT,
+                            drill_fuel_tank TEXT,
+                            drill_engine TEXT,
+                            dye TEXT,
+                            accessory_enrichment TEXT,
+                            recombobulated BOOLEAN,
+                            wood_singularity BOOLEAN,
+                            art_of_war BOOLEAN,
+                            art_of_peace BOOLEAN,
+                            etherwarp BOOLEAN,
+                            necron_scrolls TEXT[],
+                            gemstones TEXT[]
+                        )",
+                )
+                .await?;
+
+            let _ = database
+                .simple_query(
+                    "CREATE INDEX IF NOT EXISTS ended_auctions_end_t_idx ON ended_auctions (end_t)",
+                )
+                .await?;
+            let _ = database
+                .simple_query(
+                    "CREATE INDEX IF NOT EXISTS ended_auctions_item_id_idx ON ended_auctions (item_id)",
+                )
+                .await?;
+            let _ = database
+                .simple_query(
+                    "CREATE INDEX IF NOT EXISTS ended_auctions_internal_id_idx ON ended_auctions (internal_id)",
+                )
+                .await?;
+        }
+
+        if config.is_enabled(Feature::LowestbinHistory) {
+            // Create lowest bin history table if doesn't exist
+            let _ = database
+                .simple_query(
+                    "CREATE TABLE IF NOT EXISTS lowestbin_history (
+                            internal_id TEXT NOT NULL,
+                            time_t BIGINT NOT NULL,
+                            price REAL,
+                            PRIMARY KEY (internal_id, time_t)
+                        )",
+                )
+                .await?;
+
             let _ = database
                 .simple_query(
-                    "CREATE TABLE IF NOT EXISTS pets (
+                    "CREATE INDEX IF NOT EXISTS lowestbin_history_time_t_idx ON lowestbin_history (time_t)",
+                )
+                .await?;
+        }
+
+        if config.storage == StorageBackend::Postgres {
+            // Create snapshots table if doesn't exist
+            let _ = database
+                .simple_query(
+                    "CREATE TABLE IF NOT EXISTS snapshots (
                             name TEXT NOT NULL PRIMARY KEY,
+                            data BYTEA,
+                            updated BIGINT
+                        )",
+                )
+                .await?;
+        }
+
+        if config.is_enabled(Feature::Pets) {
+            // Create average pets table if doesn't exist
+            let _ = database
+                .simple_query(
+                    "CREATE TABLE IF NOT EXISTS average_pets (
+                            time_t INT,
+                            item_id TEXT,
+                            price REAL,
+                            sales REAL,
+                            PRIMARY KEY (time_t, item_id)
+                        )",
+                )
+                .await?;
+
+            let _ = database
+                .simple_query(
+                    "CREATE INDEX IF NOT EXISTS average_pets_time_t_idx ON average_pets (time_t)",
+                )
+                .await?;
+            let _ = database
+                .simple_query(
+                    "CREATE INDEX IF NOT EXISTS average_pets_item_id_idx ON average_pets (item_id)",
+                )
+                .await?;
+
+            // Create pet sales table if doesn't exist
+            let _ = database
+                .simple_query(
+                    "CREATE TABLE IF NOT EXISTS pet_sales (
+                            uuid TEXT NOT NULL PRIMARY KEY,
+                            end_t BIGINT,
+                            name TEXT,
+                            level SMALLINT,
+                            tier TEXT,
+                            tier_boost BOOLEAN,
+                            held_item TEXT,
+                            skin TEXT,
+                            candy_used SMALLINT,
                             price BIGINT,
-                            count INTEGER
+                            bin BOOLEAN
                         )",
                 )
                 .await?;
+
+            let _ = database
+                .simple_query(
+                    "CREATE INDEX IF NOT EXISTS pet_sales_name_tier_idx ON pet_sales (name, tier)",
+                )
+                .await?;
+            let _ = database
+                .simple_query("CREATE INDEX IF NOT EXISTS pet_sales_end_t_idx ON pet_sales (end_t)")
+                .await?;
         }
     }
 
     if !config.disable_updating {
-        // Remove any files from previous runs
-        let _ = fs::remove_file("lowestbin.json");
-        let _ = fs::remove_file("underbin.json");
-        let _ = fs::remove_file("query_items.json");
-
-        info(String::from("Starting auction loop..."));
-        let auction_config = config.clone();
-        start_auction_loop(move || {
-            let auction_config = auction_config.clone();
-            async move {
-                loop {
-                    let auction_config = auction_config.clone();
-                    if update_auctions(auction_config).await {
-                        break;
+        if config.replay_dir.is_empty() {
+            info(String::from("Starting auction loop..."));
+            let auction_config = config.clone();
+            start_auction_loop(config.clone(), move || {
+                let auction_config = auction_config.clone();
+                async move {
+                    loop {
+                        let auction_config = auction_config.clone();
+                        if update_auctions(auction_config).await {
+                            break;
+                        }
                     }
                 }
-            }
-        })
-        .await;
+            })
+            .await;
+        } else {
+            // Replay the recorded pages once instead of polling the Hypixel API
+            info(format!("Replaying auctions from {}...", config.replay_dir));
+            update_auctions(config.clone()).await;
+        }
     }
 
     info(String::from("Starting server..."));
@@ -0,0 +1,292 @@
+/*
+ * Rust Query API - A versatile API facade for the Hypixel Auction API
+ * Copyright (c) 2022 kr45732
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::statics::*;
+use std::fmt::Write;
+
+/// Upper bounds in seconds of the buckets of every histogram
+const BUCKETS: [f64; 13] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+
+/// Name, type, and description of each metric in the order they are written
+const FAMILIES: [(&str, &str, &str); 20] = [
+    (
+        "query_api_update_seconds",
+        "histogram",
+        "Duration of each phase (fetch, insert, or total) of auction updates",
+    ),
+    (
+        "query_api_page_seconds",
+        "histogram",
+        "Duration of fetching or parsing each page from the Hypixel API",
+    ),
+    (
+        "query_api_pages_fetched_total",
+        "counter",
+        "Pages fetched from the Hypixel API",
+    ),
+    (
+        "query_api_pages_failed_total",
+        "counter",
+        "Pages that could not be fetched or deserialized from the Hypixel API",
+    ),
+    (
+        "query_api_auctions_parsed_total",
+        "counter",
+        "Active and ended auctions parsed",
+    ),
+    (
+        "query_api_nbt_errors_total",
+        "counter",
+        "Auctions skipped because their item NBT data could not be decoded",
+    ),
+    (
+        "query_api_insert_seconds",
+        "histogram",
+        "Duration of storing the results of an update for each feature",
+    ),
+    (
+        "query_api_insert_errors_total",
+        "counter",
+        "Errors when storing the results of an update for each feature",
+    ),
+    (
+        "query_api_rows_copied_total",
+        "counter",
+        "Rows copied into each table",
+    ),
+    (
+        "query_api_updates_total",
+        "counter",
+        "Completed auction updates",
+    ),
+    (
+        "query_api_updating",
+        "gauge",
+        "1 if an auction update is running",
+    ),
+    (
+        "query_api_last_updated_timestamp_seconds",
+        "gauge",
+        "When the auctions of the last update were updated by Hypixel",
+    ),
+    (
+        "query_api_http_requests_total",
+        "counter",
+        "HTTP requests by route, method, and status",
+    ),
+    (
+        "query_api_http_request_duration_seconds",
+        "histogram",
+        "Time until the response headers of HTTP requests are sent, by route",
+    ),
+    (
+        "query_api_rate_limited_clients",
+        "gauge",
+        "Clients with a rate limit bucket that is not full",
+    ),
+    (
+        "query_api_db_pool_max_size",
+        "gauge",
+        "Max connections of the database pool",
+    ),
+    (
+        "query_api_db_pool_size",
+        "gauge",
+        "Open connections of the database pool",
+    ),
+    (
+        "query_api_db_pool_available",
+        "gauge",
+        "Idle connections of the database pool",
+    ),
+    (
+        "query_api_db_pool_waiting",
+        "gauge",
+        "Tasks waiting for a connection from the database pool",
+    ),
+    (
+        "query_api_database_requests_available",
+        "gauge",
+        "Requests that can use database connections before requests have to wait (DATABASE_REQUEST_LIMIT)",
+    ),
+];
+
+/// Observations of a histogram, where each bucket counts the observations up to its bound
+#[derive(Default)]
+pub struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+/* Adds to a counter */
+pub fn inc(name: &'static str, labels: &[(&str, &str)], value: f64) {
+    *METRICS.entry((name, format_labels(labels))).or_insert(0.0) += value;
+}
+
+pub fn set(name: &'static str, labels: &[(&str, &str)], value: f64) {
+    METRICS.insert((name, format_labels(labels)), value);
+}
+
+/* Adds a duration in seconds to a histogram */
+pub fn observe(name: &'static str, labels: &[(&str, &str)], seconds: f64) {
+    let mut histogram = HISTOGRAMS.entry((name, format_labels(labels))).or_default();
+    for (bucket, bound) in histogram.buckets.iter_mut().zip(BUCKETS) {
+        if seconds <= bound {
+            *bucket += 1;
+        }
+    }
+    histogram.sum += seconds;
+    histogram.count += 1;
+}
+
+/* Records how long storing the results of an update took for a feature and if it failed */
+pub fn record_insert(feature: &str, seconds: f64, succeeded: bool) {
+    observe("query_api_insert_seconds", &[("feature", feature)], seconds);
+    if !succeeded {
+        inc(
+            "query_api_insert_errors_total",
+            &[("feature", feature)],
+            1.0,
+        );
+    }
+}
+
+pub fn record_rows(table: &str, rows: u64) {
+    inc(
+        "query_api_rows_copied_total",
+        &[("table", table)],
+        rows as f64,
+    );
+}
+
+/* Writes every metric in the Prometheus text format after updating the gauges of the current state */
+pub async fn render() -> String {
+    set(
+        "query_api_updating",
+        &[],
+        if *IS_UPDATING.lock().await { 1.0 } else { 0.0 },
+    );
+    set(
+        "query_api_last_updated_timestamp_seconds",
+        &[],
+        *LAST_UPDATED.lock().await as f64 / 1000.0,
+    );
+    set(
+        "query_api_rate_limited_clients",
+        &[],
+        RATE_LIMITS.len() as f64,
+    );
+    set(
+        "query_api_database_requests_available",
+        &[],
+        DATABASE_REQUESTS.available_permits() as f64,
+    );
+    if let Some(database) = DATABASE.lock().await.as_ref() {
+        let status = database.status();
+        set("query_api_db_pool_max_size", &[], status.max_size as f64);
+        set("query_api_db_pool_size", &[], status.size as f64);
+        set("query_api_db_pool_available", &[], status.available as f64);
+        set("query_api_db_pool_waiting", &[], status.waiting as f64);
+    }
+
+    let mut output = String::new();
+    for (name, kind, help) in FAMILIES {
+        let _ = writeln!(output, "# HELP {} {}", name, help);
+        let _ = writeln!(output, "# TYPE {} {}", name, kind);
+
+        if kind == "histogram" {
+            let mut series = HISTOGRAMS
+                .iter()
+                .filter(|e| e.key().0 == name)
+                .map(|e| (e.key().1.clone(), e.buckets, e.sum, e.count))
+                .collect::<Vec<_>>();
+            series.sort_by(|a, b| a.0.cmp(&b.0));
+
+            for (labels, buckets, sum, count) in series {
+                for (bucket, bound) in buckets.iter().zip(BUCKETS) {
+                    let _ = writeln!(
+                        output,
+                        "{}_bucket{} {}",
+                        name,
+                        with_le(&labels, &bound.to_string()),
+                        bucket
+                    );
+                }
+                let _ = writeln!(
+                    output,
+                    "{}_bucket{} {}",
+                    name,
+                    with_le(&labels, "+Inf"),
+                    count
+                );
+                let _ = writeln!(output, "{}_sum{} {}", name, braces(&labels), sum);
+                let _ = writeln!(output, "{}_count{} {}", name, braces(&labels), count);
+            }
+        } else {
+            let mut series = METRICS
+                .iter()
+                .filter(|e| e.key().0 == name)
+                .map(|e| (e.key().1.clone(), *e.value()))
+                .collect::<Vec<_>>();
+            series.sort_by(|a, b| a.0.cmp(&b.0));
+
+            for (labels, value) in series {
+                let _ = writeln!(output, "{}{} {}", name, braces(&labels), value);
+            }
+        }
+    }
+    output
+}
+
+/* Formats labels as name="value" pairs separated by commas, escaping the values */
+fn format_labels(labels: &[(&str, &str)]) -> String {
+    labels
+        .iter()
+        .map(|(name, value)| {
+            format!(
+                "{}=\"{}\"",
+                name,
+                value
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+                    .replace('\n', "\\n")
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+fn braces(labels: &str) -> String {
+    if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", labels)
+    }
+}
+
+/* Labels of a histogram bucket, which adds the upper bound of the bucket */
+fn with_le(labels: &str, le: &str) -> String {
+    if labels.is_empty() {
+        format!("{{le=\"{}\"}}", le)
+    } else {
+        format!("{{{},le=\"{}\"}}", labels, le)
+    }
+}
@@ -0,0 +1,898 @@
+/*
+ * Rust Query API - A versatile API facade for the Hypixel Auction API
+ * Copyright (c) 2022 kr45732
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use postgres_types::ToSql;
+use serde::Deserialize;
+use serde_json::Value;
+use std::fmt::{self, Display, Formatter};
+
+/// Most conditions (including and, or, and not) a filter can have
+const MAX_CONDITIONS: usize = 64;
+
+pub type FilterParam = Box<dyn ToSql + Sync + Send>;
+
+/// Body of a POST /query request
+#[derive(Deserialize)]
+pub struct QueryRequest {
+    pub filter: Filter,
+    #[serde(default)]
+    pub sort_by: String,
+    #[serde(default)]
+    pub sort_order: String,
+    #[serde(default = "default_limit")]
+    pub limit: i64,
+}
+
+fn default_limit() -> i64 {
+    1
+}
+
+/// A filter tree compiled into the WHERE clause of a query (e.g. {"and": [{"eq": {"field": "bin", "value": true}}]})
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Filter {
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+    /// Equal to the value or null if the value is null
+    Eq(Condition),
+    Gte(Condition),
+    Lte(Condition),
+    /// Equal to any of the array of values
+    In(Condition),
+    Ilike(Condition),
+    /// Array column contains any of the array of values
+    Any(Condition),
+    /// Array column contains all of the array of values
+    All(Condition),
+}
+
+#[derive(Deserialize)]
+pub struct Condition {
+    pub field: String,
+    pub value: Value,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ColumnType {
+    Text,
+    BigInt,
+    SmallInt,
+    Real,
+    Bool,
+    TextArray,
+}
+
+impl ColumnType {
+    fn is_numeric(&self) -> bool {
+        matches!(self, Self::BigInt | Self::SmallInt | Self::Real)
+    }
+}
+
+/* Columns of the query table that can be filtered on */
+fn column_type(field: &str) -> Option<ColumnType> {
+    Some(match field {
+        "uuid"
+        | "auctioneer"
+        | "item_name"
+        | "lore"
+        | "tier"
+        | "item_id"
+        | "internal_id"
+        | "reforge"
+        | "rune"
+        | "skin"
+        | "power_scroll"
+        | "drill_upgrade_module"
+        | "drill_fuel_tank"
+        | "drill_engine"
+        | "dye"
+        | "accessory_enrichment" => ColumnType::Text,
+        "end_t" | "starting_bid" | "highest_bid" => ColumnType::BigInt,
+        "count"
+        | "potato_books"
+        | "stars"
+        | "farming_for_dummies"
+        | "transmission_tuner"
+        | "mana_disintegrator" => ColumnType::SmallInt,
+        "lowestbin_price" => ColumnType::Real,
+        "bin" | "recombobulated" | "wood_singularity" | "art_of_war" | "art_of_peace"
+        | "etherwarp" => ColumnType::Bool,
+        "enchants" | "attributes" | "necron_scrolls" | "gemstones" => ColumnType::TextArray,
+        _ => return None,
+    })
+}
+
+impl Filter {
+    /// Compiles the filter into SQL with placeholders starting at $1 for the pushed parameters
+    pub fn to_sql(&self, params: &mut Vec<FilterParam>) -> Result<String, String> {
+        let mut conditions = 0;
+        self.compile(params, &mut conditions)
+    }
+
+    fn compile(
+        &self,
+        params: &mut Vec<FilterParam>,
+        conditions: &mut usize,
+    ) -> Result<String, String> {
+        *conditions += 1;
+        if *conditions > MAX_CONDITIONS {
+            return Err(format!(
+                "Filter cannot have more than {} conditions",
+                MAX_CONDITIONS
+            ));
+        }
+
+        Ok(match self {
+            Self::And(filters) => join(filters, " AND ", "TRUE", params, conditions)?,
+            Self::Or(filters) => join(filters, " OR ", "FALSE", params, conditions)?,
+            Self::Not(filter) => format!("NOT ({})", filter.compile(params, conditions)?),
+            Self::Eq(condition) => {
+                let column_type = condition.column_type(|e| *e != ColumnType::TextArray, "eq")?;
+                if condition.value.is_null() {
+                    format!("{} IS NULL", condition.field)
+                } else {
+                    params.push(to_param(column_type, condition)?);
+                    format!("{} = ${}", condition.field, params.len())
+                }
+            }
+            Self::Gte(condition) => {
+                let column_type = condition.column_type(ColumnType::is_numeric, "gte")?;
+                params.push(to_param(column_type, condition)?);
+                format!("{} >= ${}", condition.field, params.len())
+            }
+            Self::Lte(condition) => {
+                let column_type = condition.column_type(ColumnType::is_numeric, "lte")?;
+                params.push(to_param(column_type, condition)?);
+                format!("{} <= ${}", condition.field, params.len())
+            }
+            Self::In(condition) => {
+                let column_type = condition.column_type(|e| *e != ColumnType::TextArray, "in")?;
+                params.push(to_array_param(column_type, condition)?);
+                format!("{} = ANY(${})", condition.field, params.len())
+            }
+            Self::Ilike(condition) => {
+                let column_type = condition.column_type(|e| *e == ColumnType::Text, "ilike")?;
+                params.push(to_param(column_type, condition)?);
+                format!("{} ILIKE ${}", condition.field, params.len())
+            }
+            Self::Any(condition) => {
+                condition.column_type(|e| *e == ColumnType::TextArray, "any")?;
+                params.push(to_array_param(ColumnType::Text, condition)?);
+                format!("{} && ${}", condition.field, params.len())
+            }
+            Self::All(condition) => {
+                condition.column_type(|e| *e == ColumnType::TextArray, "all")?;
+                params.push(to_array_param(ColumnType::Text, condition)?);
+                format!("{} @> ${}", condition.field, params.len())
+            }
+        })
+    }
+}
+
+impl Condition {
+    /* Gets the type of the field if it exists and supports the operator */
+    fn column_type(
+        &self,
+        supports: impl Fn(&ColumnType) -> bool,
+        operator: &str,
+    ) -> Result<ColumnType, String> {
+        match column_type(&self.field) {
+            Some(column_type) if supports(&column_type) => Ok(column_type),
+            Some(_) => Err(format!(
+                "Field {} does not support the {} operator",
+                self.field, operator
+            )),
+            None => Err(format!("Unknown field {}", self.field)),
+        }
+    }
+}
+
+fn join(
+    filters: &[Filter],
+    separator: &str,
+    empty: &str,
+    params: &mut Vec<FilterParam>,
+    conditions: &mut usize,
+) -> Result<String, String> {
+    if filters.is_empty() {
+        return Ok(empty.to_string());
+    }
+
+    let mut compiled = Vec::new();
+    for filter in filters {
+        compiled.push(format!("({})", filter.compile(params, conditions)?));
+    }
+    Ok(compiled.join(separator))
+}
+
+fn to_param(column_type: ColumnType, condition: &Condition) -> Result<FilterParam, String> {
+    let value = &condition.value;
+    let param: Option<FilterParam> = match column_type {
+        ColumnType::Text => value
+            .as_str()
+            .map(|e| Box::new(e.to_string()) as FilterParam),
+        ColumnType::BigInt => value.as_i64().map(|e| Box::new(e) as FilterParam),
+        ColumnType::SmallInt => value
+            .as_i64()
+            .and_then(|e| i16::try_from(e).ok())
+            .map(|e| Box::new(e) as FilterParam),
+        ColumnType::Real => value.as_f64().map(|e| Box::new(e as f32) as FilterParam),
+        ColumnType::Bool => value.as_bool().map(|e| Box::new(e) as FilterParam),
+        ColumnType::TextArray => None,
+    };
+    param.ok_or_else(|| format!("Invalid value for field {}: {}", condition.field, value))
+}
+
+fn to_array_param(column_type: ColumnType, condition: &Condition) -> Result<FilterParam, String> {
+    let values = condition
+        .value
+        .as_array()
+        .ok_or_else(|| format!("Value for field {} must be an array", condition.field))?;
+    let invalid = || {
+        format!(
+            "Invalid value for field {}: {}",
+            condition.field, condition.value
+        )
+    };
+
+    Ok(match column_type {
+        ColumnType::Text | ColumnType::TextArray => Box::new(
+            values
+                .iter()
+                .map(|e| e.as_str().map(|e| e.to_string()))
+                .collect::<Option<Vec<String>>>()
+                .ok_or_else(invalid)?,
+        ),
+        ColumnType::BigInt => Box::new(
+            values
+                .iter()
+                .map(|e| e.as_i64())
+                .collect::<Option<Vec<i64>>>()
+                .ok_or_else(invalid)?,
+        ),
+        ColumnType::SmallInt => Box::new(
+            values
+                .iter()
+                .map(|e| e.as_i64().and_then(|e| i16::try_from(e).ok()))
+                .collect::<Option<Vec<i16>>>()
+                .ok_or_else(invalid)?,
+        ),
+        ColumnType::Real => Box::new(
+            values
+                .iter()
+                .map(|e| e.as_f64().map(|e| e as f32))
+                .collect::<Option<Vec<f32>>>()
+                .ok_or_else(invalid)?,
+        ),
+        ColumnType::Bool => Box::new(
+            values
+                .iter()
+                .map(|e| e.as_bool())
+                .collect::<Option<Vec<bool>>>()
+                .ok_or_else(invalid)?,
+        ),
+    })
+}
+
+#[derive(Clone, PartialEq)]
+enum Token {
+    /// Field name or keyword such as AND
+    Word(String),
+    Literal(Value),
+    Symbol(&'static str),
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Word(word) => write!(f, "{}", word),
+            Self::Literal(value) => write!(f, "{}", value),
+            Self::Symbol(symbol) => write!(f, "{}", symbol),
+        }
+    }
+}
+
+/* Splits an expression into words, literals, and symbols */
+fn tokenize(query: &str) -> Result<Vec<Token>, String> {
+    let chars = query.chars().collect::<Vec<char>>();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Word(chars[start..i].iter().collect()));
+        } else if c.is_ascii_digit()
+            || (c == '-' && chars.get(i + 1).is_some_and(char::is_ascii_digit))
+        {
+            let start = i;
+            i += 1;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            let number = chars[start..i].iter().collect::<String>();
+            tokens.push(Token::Literal(if let Ok(number) = number.parse::<i64>() {
+                Value::from(number)
+            } else if let Ok(number) = number.parse::<f64>() {
+                Value::from(number)
+            } else {
+                return Err(format!("Invalid number {}", number));
+            }));
+        } else if c == '\'' {
+            // Strings are single quoted with '' as an escaped quote
+            let mut string = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    Some('\'') if chars.get(i + 1) == Some(&'\'') => {
+                        string.push('\'');
+                        i += 2;
+                    }
+                    Some('\'') => {
+                        i += 1;
+                        break;
+                    }
+                    Some(c) => {
+                        string.push(*c);
+                        i += 1;
+                    }
+                    None => return Err(String::from("Unterminated string")),
+                }
+            }
+            tokens.push(Token::Literal(Value::from(string)));
+        } else {
+            let symbol = [
+                "!=", "<>", ">=", "<=", "@>", "&&", "=", ">", "<", "(", ")", "[", "]", ",",
+            ]
+            .into_iter()
+            .find(|e| chars[i..].starts_with(&e.chars().collect::<Vec<char>>()))
+            .ok_or_else(|| format!("Unexpected character {}", c))?;
+            i += symbol.len();
+            tokens.push(Token::Symbol(symbol));
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Parses an admin query expression (e.g. stars >= 5 AND enchants @> ARRAY['ULTIMATE_WISE;5']) into a filter
+pub fn parse_filter(query: &str) -> Result<Filter, String> {
+    let mut parser = Parser {
+        tokens: tokenize(query)?,
+        position: 0,
+        depth: 0,
+    };
+    let filter = parser.or()?;
+    match parser.peek() {
+        Some(token) => Err(format!("Unexpected {}", token)),
+        None => Ok(filter),
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    /// Nesting of parentheses and NOT, limited to prevent stack overflows
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        let token = self
+            .peek()
+            .cloned()
+            .ok_or_else(|| String::from("Unexpected end of query"))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    /* Consumes the next token if it is the keyword (case insensitive) */
+    fn keyword(&mut self, keyword: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword)) {
+            self.position += 1;
+            return true;
+        }
+        false
+    }
+
+    /* Consumes the next token if it is the symbol */
+    fn symbol(&mut self, symbol: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Symbol(e)) if *e == symbol) {
+            self.position += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), String> {
+        if self.symbol(symbol) {
+            Ok(())
+        } else {
+            Err(format!("Expected {}", symbol))
+        }
+    }
+
+    fn nest(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        if self.depth > MAX_CONDITIONS {
+            return Err(format!(
+                "Query cannot be nested more than {} times",
+                MAX_CONDITIONS
+            ));
+        }
+        Ok(())
+    }
+
+    fn or(&mut self) -> Result<Filter, String> {
+        let mut filters = vec![self.and()?];
+        while self.keyword("OR") {
+            filters.push(self.and()?);
+        }
+        Ok(if filters.len() == 1 {
+            filters.remove(0)
+        } else {
+            Filter::Or(filters)
+        })
+    }
+
+    fn and(&mut self) -> Result<Filter, String> {
+        let mut filters = vec![self.unary()?];
+        while self.keyword("AND") {
+            filters.push(self.unary()?);
+        }
+        Ok(if filters.len() == 1 {
+            filters.remove(0)
+        } else {
+            Filter::And(filters)
+        })
+    }
+
+    fn unary(&mut self) -> Result<Filter, String> {
+        if self.keyword("NOT") {
+            self.nest()?;
+            let filter = Filter::Not(Box::new(self.unary()?));
+            self.depth -= 1;
+            Ok(filter)
+        } else if self.symbol("(") {
+            self.nest()?;
+            let filter = self.or()?;
+            self.expect(")")?;
+            self.depth -= 1;
+            Ok(filter)
+        } else {
+            self.comparison()
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Filter, String> {
+        let field = match self.next()? {
+            Token::Word(word) => word.to_lowercase(),
+            token => return Err(format!("Expected a field but found {}", token)),
+        };
+        let condition = |value| Condition {
+            field: field.clone(),
+            value,
+        };
+
+        if self.keyword("IS") {
+            let not = self.keyword("NOT");
+            if !self.keyword("NULL") {
+                return Err(String::from("Expected NULL"));
+            }
+            let filter = Filter::Eq(condition(Value::Null));
+            return Ok(if not {
+                Filter::Not(Box::new(filter))
+            } else {
+                filter
+            });
+        }
+
+        let not = self.keyword("NOT");
+        if self.keyword("ILIKE") {
+            let filter = Filter::Ilike(condition(self.literal()?));
+            return Ok(if not {
+                Filter::Not(Box::new(filter))
+            } else {
+                filter
+            });
+        }
+        if self.keyword("IN") {
+            self.expect("(")?;
+            let filter = Filter::In(condition(self.list(")")?));
+            return Ok(if not {
+                Filter::Not(Box::new(filter))
+            } else {
+                filter
+            });
+        }
+        if not {
+            return Err(String::from("Expected ILIKE or IN after NOT"));
+        }
+
+        Ok(match self.next()? {
+            Token::Symbol("=") => Filter::Eq(condition(self.literal()?)),
+            Token::Symbol("!=") | Token::Symbol("<>") => {
+                Filter::Not(Box::new(Filter::Eq(condition(self.literal()?))))
+            }
+            Token::Symbol(">=") => Filter::Gte(condition(self.literal()?)),
+            Token::Symbol("<=") => Filter::Lte(condition(self.literal()?)),
+            Token::Symbol(">") => Filter::Not(Box::new(Filter::Lte(condition(self.literal()?)))),
+            Token::Symbol("<") => Filter::Not(Box::new(Filter::Gte(condition(self.literal()?)))),
+            Token::Symbol("@>") => Filter::All(condition(self.array()?)),
+            Token::Symbol("&&") => Filter::Any(condition(self.array()?)),
+            token => return Err(format!("Unknown operator {}", token)),
+        })
+    }
+
+    fn literal(&mut self) -> Result<Value, String> {
+        match self.next()? {
+            Token::Literal(value) => Ok(value),
+            Token::Word(word) if word.eq_ignore_ascii_case("TRUE") => Ok(Value::Bool(true)),
+            Token::Word(word) if word.eq_ignore_ascii_case("FALSE") => Ok(Value::Bool(false)),
+            Token::Word(word) if word.eq_ignore_ascii_case("NULL") => Ok(Value::Null),
+            token => Err(format!("Expected a value but found {}", token)),
+        }
+    }
+
+    /* Parses ARRAY[...] */
+    fn array(&mut self) -> Result<Value, String> {
+        if !self.keyword("ARRAY") {
+            return Err(String::from("Expected ARRAY"));
+        }
+        self.expect("[")?;
+        self.list("]")
+    }
+
+    /* Parses comma separated values until the closing symbol */
+    fn list(&mut self, close: &str) -> Result<Value, String> {
+        let mut values = Vec::new();
+        if !self.symbol(close) {
+            loop {
+                values.push(self.literal()?);
+                if self.symbol(close) {
+                    break;
+                }
+                if !self.symbol(",") {
+                    return Err(format!("Expected , or {}", close));
+                }
+            }
+        }
+        Ok(Value::Array(values))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /* Compiles a JSON filter into its SQL and the debug output of its parameters */
+    fn compile(filter: Value) -> Result<(String, Vec<String>), String> {
+        let filter: Filter = serde_json::from_value(filter).unwrap();
+        let mut params = Vec::new();
+        let sql = filter.to_sql(&mut params)?;
+        Ok((sql, params.iter().map(|e| format!("{:?}", e)).collect()))
+    }
+
+    /* Parses an admin query expression and compiles it like compile */
+    fn parse(query: &str) -> Result<(String, Vec<String>), String> {
+        let mut params = Vec::new();
+        let sql = parse_filter(query)?.to_sql(&mut params)?;
+        Ok((sql, params.iter().map(|e| format!("{:?}", e)).collect()))
+    }
+
+    #[test]
+    fn eq() {
+        assert_eq!(
+            compile(json!({ "eq": { "field": "item_id", "value": "HYPERION" } })).unwrap(),
+            (
+                String::from("item_id = $1"),
+                vec![String::from("\"HYPERION\"")]
+            )
+        );
+    }
+
+    #[test]
+    fn eq_null() {
+        assert_eq!(
+            compile(json!({ "eq": { "field": "reforge", "value": null } })).unwrap(),
+            (String::from("reforge IS NULL"), vec![])
+        );
+    }
+
+    #[test]
+    fn gte_and_lte() {
+        assert_eq!(
+            compile(json!({ "and": [
+                { "gte": { "field": "starting_bid", "value": 1000 } },
+                { "lte": { "field": "stars", "value": 5 } },
+                { "gte": { "field": "lowestbin_price", "value": 2.5 } }
+            ] }))
+            .unwrap(),
+            (
+                String::from("(starting_bid >= $1) AND (stars <= $2) AND (lowestbin_price >= $3)"),
+                vec![String::from("1000"), String::from("5"), String::from("2.5")]
+            )
+        );
+    }
+
+    #[test]
+    fn or_and_not() {
+        assert_eq!(
+            compile(json!({ "or": [
+                { "eq": { "field": "bin", "value": true } },
+                { "not": { "eq": { "field": "tier", "value": "EPIC" } } }
+            ] }))
+            .unwrap(),
+            (
+                String::from("(bin = $1) OR (NOT (tier = $2))"),
+                vec![String::from("true"), String::from("\"EPIC\"")]
+            )
+        );
+    }
+
+    #[test]
+    fn in_values() {
+        assert_eq!(
+            compile(json!({ "in": { "field": "tier", "value": ["LEGENDARY", "MYTHIC"] } }))
+                .unwrap(),
+            (
+                String::from("tier = ANY($1)"),
+                vec![String::from("[\"LEGENDARY\", \"MYTHIC\"]")]
+            )
+        );
+    }
+
+    #[test]
+    fn ilike() {
+        assert_eq!(
+            compile(json!({ "ilike": { "field": "item_name", "value": "%hyperion%" } })).unwrap(),
+            (
+                String::from("item_name ILIKE $1"),
+                vec![String::from("\"%hyperion%\"")]
+            )
+        );
+    }
+
+    #[test]
+    fn any_and_all() {
+        assert_eq!(
+            compile(json!({ "and": [
+                { "any": { "field": "enchants", "value": ["ULTIMATE_WISE;5"] } },
+                { "all": { "field": "gemstones", "value": ["COMBAT_0_FINE_JASPER_GEM"] } }
+            ] }))
+            .unwrap(),
+            (
+                String::from("(enchants && $1) AND (gemstones @> $2)"),
+                vec![
+                    String::from("[\"ULTIMATE_WISE;5\"]"),
+                    String::from("[\"COMBAT_0_FINE_JASPER_GEM\"]")
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn empty_in_and_or() {
+        assert_eq!(
+            compile(json!({ "in": { "field": "stars", "value": [] } })).unwrap(),
+            (String::from("stars = ANY($1)"), vec![String::from("[]")])
+        );
+        assert_eq!(
+            compile(json!({ "and": [] })).unwrap(),
+            (String::from("TRUE"), vec![])
+        );
+        assert_eq!(
+            compile(json!({ "or": [] })).unwrap(),
+            (String::from("FALSE"), vec![])
+        );
+    }
+
+    #[test]
+    fn unknown_field() {
+        assert_eq!(
+            compile(json!({ "eq": { "field": "bids", "value": "a" } })).unwrap_err(),
+            "Unknown field bids"
+        );
+        assert_eq!(
+            compile(json!({ "eq": { "field": "1=1; DROP TABLE query", "value": 1 } })).unwrap_err(),
+            "Unknown field 1=1; DROP TABLE query"
+        );
+    }
+
+    #[test]
+    fn unsupported_operator() {
+        assert_eq!(
+            compile(json!({ "gte": { "field": "item_id", "value": 1 } })).unwrap_err(),
+            "Field item_id does not support the gte operator"
+        );
+        assert_eq!(
+            compile(json!({ "any": { "field": "tier", "value": ["EPIC"] } })).unwrap_err(),
+            "Field tier does not support the any operator"
+        );
+    }
+
+    #[test]
+    fn invalid_value() {
+        assert_eq!(
+            compile(json!({ "eq": { "field": "stars", "value": "5" } })).unwrap_err(),
+            "Invalid value for field stars: \"5\""
+        );
+        assert_eq!(
+            compile(json!({ "lte": { "field": "stars", "value": 100000 } })).unwrap_err(),
+            "Invalid value for field stars: 100000"
+        );
+        assert_eq!(
+            compile(json!({ "in": { "field": "tier", "value": "EPIC" } })).unwrap_err(),
+            "Value for field tier must be an array"
+        );
+        assert_eq!(
+            compile(json!({ "all": { "field": "enchants", "value": [1] } })).unwrap_err(),
+            "Invalid value for field enchants: [1]"
+        );
+    }
+
+    #[test]
+    fn too_many_conditions() {
+        let condition = json!({ "eq": { "field": "bin", "value": true } });
+        // The and counts as a condition too
+        assert!(compile(json!({ "and": vec![condition.clone(); MAX_CONDITIONS - 1] })).is_ok());
+        assert_eq!(
+            compile(json!({ "and": vec![condition; MAX_CONDITIONS] })).unwrap_err(),
+            "Filter cannot have more than 64 conditions"
+        );
+    }
+
+    #[test]
+    fn parse_quoted_string() {
+        assert_eq!(
+            parse("item_name = 'Hyperion''s Edge'").unwrap(),
+            (
+                String::from("item_name = $1"),
+                vec![String::from("\"Hyperion's Edge\"")]
+            )
+        );
+        assert_eq!(
+            parse("item_name = 'Hyperion").unwrap_err(),
+            "Unterminated string"
+        );
+    }
+
+    #[test]
+    fn parse_negated_operators() {
+        assert_eq!(parse("tier != 'EPIC'").unwrap().0, "NOT (tier = $1)");
+        assert_eq!(parse("tier <> 'EPIC'").unwrap().0, "NOT (tier = $1)");
+        assert_eq!(parse("stars > 3").unwrap().0, "NOT (stars <= $1)");
+        assert_eq!(parse("stars < 3").unwrap().0, "NOT (stars >= $1)");
+    }
+
+    #[test]
+    fn parse_null() {
+        assert_eq!(
+            parse("reforge IS NULL").unwrap(),
+            (String::from("reforge IS NULL"), vec![])
+        );
+        assert_eq!(
+            parse("reforge is not null").unwrap(),
+            (String::from("NOT (reforge IS NULL)"), vec![])
+        );
+    }
+
+    #[test]
+    fn parse_in() {
+        assert_eq!(
+            parse("tier IN ('EPIC', 'LEGENDARY')").unwrap(),
+            (
+                String::from("tier = ANY($1)"),
+                vec![String::from("[\"EPIC\", \"LEGENDARY\"]")]
+            )
+        );
+        assert_eq!(
+            parse("stars NOT IN (4, 5)").unwrap(),
+            (
+                String::from("NOT (stars = ANY($1))"),
+                vec![String::from("[4, 5]")]
+            )
+        );
+    }
+
+    #[test]
+    fn parse_array() {
+        assert_eq!(
+            parse("enchants @> ARRAY['ULTIMATE_WISE;5', 'GROWTH;6']").unwrap(),
+            (
+                String::from("enchants @> $1"),
+                vec![String::from("[\"ULTIMATE_WISE;5\", \"GROWTH;6\"]")]
+            )
+        );
+        assert_eq!(
+            parse("enchants && ARRAY['ULTIMATE_WISE;5']").unwrap().0,
+            "enchants && $1"
+        );
+        assert_eq!(
+            parse("enchants @> ('ULTIMATE_WISE;5')").unwrap_err(),
+            "Expected ARRAY"
+        );
+    }
+
+    #[test]
+    fn parse_precedence() {
+        assert_eq!(
+            parse("bin = true OR stars >= 5 AND tier = 'EPIC'")
+                .unwrap()
+                .0,
+            "(bin = $1) OR ((stars >= $2) AND (tier = $3))"
+        );
+        assert_eq!(
+            parse("(bin = true OR stars >= 5) AND tier = 'EPIC'")
+                .unwrap()
+                .0,
+            "((bin = $1) OR (stars >= $2)) AND (tier = $3)"
+        );
+        assert_eq!(
+            parse("NOT bin = true AND stars >= 5").unwrap().0,
+            "(NOT (bin = $1)) AND (stars >= $2)"
+        );
+    }
+
+    #[test]
+    fn parse_rejects_unknown_field() {
+        assert_eq!(
+            parse("password = 'a'").unwrap_err(),
+            "Unknown field password"
+        );
+    }
+
+    #[test]
+    fn parse_rejects_subquery() {
+        assert_eq!(
+            parse("stars = (SELECT MAX(stars) FROM query)").unwrap_err(),
+            "Expected a value but found ("
+        );
+        assert_eq!(
+            parse("uuid IN (SELECT uuid FROM query)").unwrap_err(),
+            "Expected a value but found SELECT"
+        );
+    }
+
+    #[test]
+    fn parse_rejects_statements() {
+        assert_eq!(
+            parse("stars = 5; DELETE FROM query").unwrap_err(),
+            "Unexpected character ;"
+        );
+    }
+
+    #[test]
+    fn parse_rejects_trailing_tokens() {
+        assert_eq!(parse("stars = 5 stars").unwrap_err(), "Unexpected stars");
+        assert_eq!(parse("(stars = 5))").unwrap_err(), "Unexpected )");
+        assert_eq!(parse("(stars = 5").unwrap_err(), "Expected )");
+    }
+}
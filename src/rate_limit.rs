@@ -0,0 +1,74 @@
+/*
+ * Rust Query API - A versatile API facade for the Hypixel Auction API
+ * Copyright (c) 2022 kr45732
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::{config::Config, statics::RATE_LIMITS};
+use std::{net::IpAddr, time::Instant};
+
+/// Requests a client can make right now, refilled at the rate limit up to the burst
+pub struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /* Tokens after refilling for the time since the bucket was last updated */
+    fn refilled(&self, config: &Config, now: Instant) -> f64 {
+        (self.tokens + now.duration_since(self.updated).as_secs_f64() * config.rate_limit)
+            .min(config.rate_limit_burst)
+    }
+}
+
+/* Takes a token from the bucket of the client, or returns the seconds until the client can make another request */
+pub fn take_token(config: &Config, client: String) -> Result<(), u64> {
+    let now = Instant::now();
+    let mut bucket = RATE_LIMITS.entry(client).or_insert(TokenBucket {
+        tokens: config.rate_limit_burst,
+        updated: now,
+    });
+
+    bucket.tokens = bucket.refilled(config, now);
+    bucket.updated = now;
+    if bucket.tokens >= 1.0 {
+        bucket.tokens -= 1.0;
+        Ok(())
+    } else {
+        Err(((1.0 - bucket.tokens) / config.rate_limit).ceil() as u64)
+    }
+}
+
+/* Removes buckets that have refilled, since they are the same as the bucket of a new client */
+pub fn remove_full_buckets(config: &Config) {
+    let now = Instant::now();
+    RATE_LIMITS.retain(|_, bucket| bucket.refilled(config, now) < config.rate_limit_burst);
+}
+
+/* Gets the IP of the client, which is from the X-Forwarded-For header if the request is from a trusted proxy */
+pub fn client_ip(config: &Config, remote: IpAddr, forwarded_for: Option<&str>) -> IpAddr {
+    // Each proxy appends the IP it received the request from, so the client is the last IP that is not a trusted proxy
+    let mut ip = remote;
+    for forwarded in forwarded_for.unwrap_or_default().rsplit(',') {
+        if !config.trusts_proxy(ip) {
+            break;
+        }
+        match forwarded.trim().parse::<IpAddr>() {
+            Ok(forwarded) => ip = forwarded,
+            Err(_) => break,
+        }
+    }
+    ip
+}
@@ -17,17 +17,27 @@
  */
 
 use crate::{
+    api_keys::{
+        generate_key, hash_key, record_usage, refresh_api_keys, seconds_until_quota_reset, Auth,
+    },
+    compression::{Encoding, MIN_COMPRESS_SIZE},
     config::{Config, Feature},
+    format::{map_rows, Format, RowWriter},
+    metrics::{inc, observe, render},
+    query_filter::{parse_filter, FilterParam, QueryRequest},
+    rate_limit::{client_ip, remove_full_buckets, take_token},
     statics::*,
+    storage::{get_snapshot, Dataset, StorageBackend},
     structs::*,
     utils::*,
 };
 use dashmap::DashMap;
-use futures::TryStreamExt;
-use http_body_util::{combinators::BoxBody, BodyExt, Full};
+use deadpool_postgres::{Client, Object};
+use futures::{stream, StreamExt, TryStreamExt};
+use http_body_util::{combinators::BoxBody, BodyExt, Full, Limited, StreamBody};
 use hyper::{
-    body::{Body, Bytes},
-    header,
+    body::{Body, Bytes, Frame},
+    header::{self, HeaderValue},
     service::service_fn,
     Error, Method, Request, Response, StatusCode,
 };
@@ -36,25 +46,61 @@ use hyper_util::{
     server::conn::auto,
 };
 use log::info;
+use percent_encoding::percent_decode_str;
 use postgres_types::ToSql;
+use regex::Regex;
 use reqwest::Url;
 use serde::Serialize;
-use serde_json::json;
-use std::{fs, net::SocketAddr, sync::Arc};
-use tokio::net::TcpListener;
-use tokio_postgres::Row;
+use serde_json::{json, Value};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+};
+use tokio::{
+    net::TcpListener,
+    sync::SemaphorePermit,
+    time::{self, Duration, Instant},
+};
+use tokio_postgres::{Row, RowStream};
+use tokio_util::io::{ReaderStream, StreamReader};
+
+/// How long a request waits for a database permit before getting a 503 response
+const DATABASE_WAIT: Duration = Duration::from_secs(5);
+
+/// Max limit of /query and /sales without the admin key
+const MAX_LIMIT: i64 = 500;
+
+/// Methods the router handles
+const ALLOWED_METHODS: &str = "GET, HEAD, POST, PUT, DELETE, OPTIONS";
+
+/// Errors of response bodies streamed from the database
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
 
 /// Starts the server listening on URL
-pub async fn start_server(
-    config: Arc<Config>,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+pub async fn start_server(config: Arc<Config>) -> Result<(), BoxError> {
     let address: SocketAddr = config.full_url.parse().unwrap();
     let listener = TcpListener::bind(address).await?;
 
     info(format!("Listening on http://{}", address));
 
+    DATABASE_REQUESTS.add_permits(config.database_request_limit);
+    if config.rate_limit > 0.0 {
+        // Forget clients that stopped making requests
+        let rate_limit_config = config.clone();
+        tokio::spawn(async move {
+            let mut interval = time::interval(Duration::from_secs(60));
+            loop {
+                interval.tick().await;
+                remove_full_buckets(&rate_limit_config);
+            }
+        });
+    }
+
     loop {
-        let (tcp, _) = listener.accept().await?;
+        let (tcp, remote) = listener.accept().await?;
         let io = TokioIo::new(tcp);
         let captured_config = config.clone();
 
@@ -62,7 +108,9 @@ pub async fn start_server(
             if let Err(err) = auto::Builder::new(TokioExecutor::new())
                 .serve_connection(
                     io,
-                    service_fn(move |req| handle_response(captured_config.clone(), req)),
+                    service_fn(move |req| {
+                        handle_response(captured_config.clone(), remote.ip(), req)
+                    }),
                 )
                 .await
             {
@@ -75,10 +123,209 @@ pub async fn start_server(
 /* Handles http requests to the server */
 async fn handle_response(
     config: Arc<Config>,
-    req: Request<impl Body>,
-) -> Result<Response<BoxBody<Bytes, Error>>, Error> {
+    remote: IpAddr,
+    mut req: Request<impl Body<Error = impl Into<BoxError>>>,
+) -> Result<Response<BoxBody<Bytes, BoxError>>, Error> {
+    let started = Instant::now();
+    let route_label = route_name(req.uri().path()).to_string();
+    // Other methods are grouped so clients cannot create unlimited metrics
+    let method = match *req.method() {
+        Method::GET
+        | Method::HEAD
+        | Method::POST
+        | Method::PUT
+        | Method::DELETE
+        | Method::OPTIONS => req.method().as_str().to_string(),
+        _ => String::from("other"),
+    };
+
+    let encoding = Encoding::negotiate(
+        req.headers()
+            .get(header::ACCEPT_ENCODING)
+            .and_then(|e| e.to_str().ok()),
+    );
+    let origin = req.headers().get(header::ORIGIN).cloned();
+
+    // HEAD requests get the headers of the GET response without its body
+    let head = req.method() == Method::HEAD;
+    if head {
+        *req.method_mut() = Method::GET;
+    }
+
+    let mut response = if req.method() == Method::OPTIONS {
+        options(&config, &req)
+    } else {
+        compress_response(encoding, route(config.clone(), remote, req).await?)
+    };
+    cors_headers(&config, origin, &mut response);
+
+    inc(
+        "query_api_http_requests_total",
+        &[
+            ("route", &route_label),
+            ("method", &method),
+            ("status", response.status().as_str()),
+        ],
+        1.0,
+    );
+    observe(
+        "query_api_http_request_duration_seconds",
+        &[("route", &route_label)],
+        started.elapsed().as_secs_f64(),
+    );
+
+    if head {
+        let (mut parts, body) = response.into_parts();
+        if let Some(length) = body.size_hint().exact() {
+            parts.headers.insert(header::CONTENT_LENGTH, length.into());
+        }
+        return Ok(Response::from_parts(parts, empty_body()));
+    }
+    Ok(response)
+}
+
+/* Responds to OPTIONS requests with the allowed methods, including CORS preflight requests from browsers */
+fn options(config: &Config, req: &Request<impl Body>) -> Response<BoxBody<Bytes, BoxError>> {
+    let mut response = Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .header(header::ALLOW, ALLOWED_METHODS);
+
+    let preflight = req
+        .headers()
+        .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+    let allowed_origin = req
+        .headers()
+        .get(header::ORIGIN)
+        .and_then(|e| e.to_str().ok())
+        .is_some_and(|e| config.cors_origin(e).is_some());
+    if preflight && allowed_origin {
+        response = response
+            .header(header::ACCESS_CONTROL_ALLOW_METHODS, ALLOWED_METHODS)
+            .header(
+                header::ACCESS_CONTROL_ALLOW_HEADERS,
+                &config.cors_allowed_headers,
+            )
+            .header(header::ACCESS_CONTROL_MAX_AGE, config.cors_max_age);
+    }
+
+    response.body(empty_body()).unwrap()
+}
+
+/* Adds the CORS headers if the request is from an allowed origin */
+fn cors_headers(
+    config: &Config,
+    origin: Option<HeaderValue>,
+    response: &mut Response<BoxBody<Bytes, BoxError>>,
+) {
+    if config.cors_allowed_origins.is_empty() {
+        return;
+    }
+
+    let headers = response.headers_mut();
+    // The response depends on the origin unless every origin is allowed
+    if !config.cors_allowed_origins.iter().any(|e| e == "*") {
+        headers.append(header::VARY, HeaderValue::from_static("Origin"));
+    }
+
+    let Some(allow_origin) = origin
+        .as_ref()
+        .and_then(|e| e.to_str().ok())
+        .and_then(|e| config.cors_origin(e))
+        .and_then(|e| HeaderValue::from_str(e).ok())
+    else {
+        return;
+    };
+    headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+    headers.insert(
+        header::ACCESS_CONTROL_EXPOSE_HEADERS,
+        HeaderValue::from_static("ETag, Last-Modified, X-Next-Cursor"),
+    );
+}
+
+/* Authenticates and limits requests before passing them to the handler of their path */
+async fn route(
+    config: Arc<Config>,
+    remote: IpAddr,
+    req: Request<impl Body<Error = impl Into<BoxError>>>,
+) -> Result<Response<BoxBody<Bytes, BoxError>>, Error> {
     info!("{} {}", req.method(), req.uri().path());
 
+    let auth = Auth::from_key(&config, &request_key(&config, &req));
+
+    if config.rate_limit > 0.0 {
+        // Clients can share API_KEY, so only keys from the api_keys table get their own limit
+        let client = match &auth.api_key {
+            Some(api_key) => format!("key:{}", api_key.name),
+            None => format!(
+                "ip:{}",
+                client_ip(
+                    &config,
+                    remote,
+                    req.headers()
+                        .get("X-Forwarded-For")
+                        .and_then(|e| e.to_str().ok()),
+                )
+            ),
+        };
+        if let Err(retry_after) = take_token(&config, client) {
+            return retry_later(
+                StatusCode::TOO_MANY_REQUESTS,
+                "Rate limit exceeded",
+                retry_after,
+            );
+        }
+    }
+
+    let permit = if uses_database(&config, &auth, req.uri().path()) {
+        match time::timeout(DATABASE_WAIT, DATABASE_REQUESTS.acquire()).await {
+            Ok(Ok(permit)) => Some(permit),
+            _ => {
+                return retry_later(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "Too many database requests",
+                    1,
+                )
+            }
+        }
+    } else {
+        None
+    };
+
+    if let Some(response) = check_api_key(&auth, req.uri().path()).await? {
+        return Ok(response);
+    }
+
+    let response = dispatch(config, &auth, req).await?;
+    Ok(match permit {
+        Some(permit) => hold_permit(response, permit),
+        None => response,
+    })
+}
+
+/* Passes requests to the handler of their path */
+async fn dispatch(
+    config: Arc<Config>,
+    auth: &Auth,
+    req: Request<impl Body<Error = impl Into<BoxError>>>,
+) -> Result<Response<BoxBody<Bytes, BoxError>>, Error> {
+    if req.uri().path() == "/api_keys" {
+        return match *req.method() {
+            Method::GET => api_keys(auth).await,
+            Method::POST => create_api_key(auth, req).await,
+            Method::PUT => update_api_key(auth, req).await,
+            Method::DELETE => delete_api_key(config, auth, req).await,
+            _ => not_implemented(),
+        };
+    }
+
+    if req.method() == Method::POST && req.uri().path() == "/query" {
+        return if config.is_enabled(Feature::Query) {
+            query_post(config, auth, req).await
+        } else {
+            bad_request("Query feature is not enabled")
+        };
+    }
+
     if req.method() != Method::GET {
         return not_implemented();
     }
@@ -87,49 +334,70 @@ async fn handle_response(
         "/" => base(config).await,
         "/query" => {
             if config.is_enabled(Feature::Query) {
-                query(config, req).await
+                query(config, auth, req).await
             } else {
                 bad_request("Query feature is not enabled")
             }
         }
+        "/sales" => {
+            if config.is_enabled(Feature::Sales) {
+                sales(config, auth, req).await
+            } else {
+                bad_request("Sales feature is not enabled")
+            }
+        }
         "/query_items" => {
             if config.is_enabled(Feature::Query) {
-                query_items(config, req).await
+                query_items(config, auth, req).await
             } else {
                 bad_request("Query feature is not enabled")
             }
         }
         "/pets" => {
             if config.is_enabled(Feature::Pets) {
-                pets(config, req).await
+                pets(config, auth, req).await
             } else {
                 bad_request("Pets feature is not enabled")
             }
         }
         "/lowestbin" => {
             if config.is_enabled(Feature::Lowestbin) {
-                lowestbin(config, req).await
+                lowestbin(config, auth, req).await
+            } else {
+                bad_request("Lowest bins feature is not enabled")
+            }
+        }
+        "/lowestbin/history" => {
+            if config.is_enabled(Feature::LowestbinHistory) {
+                lowestbin_history(config, auth, req).await
+            } else {
+                bad_request("Lowest bin history feature is not enabled")
+            }
+        }
+        path if path.starts_with("/lowestbin/") => {
+            if config.is_enabled(Feature::Lowestbin) {
+                lowestbin_item(config, auth, req).await
             } else {
                 bad_request("Lowest bins feature is not enabled")
             }
         }
         "/underbin" => {
             if config.is_enabled(Feature::Underbin) {
-                underbin(config, req).await
+                underbin(config, auth, req).await
             } else {
                 bad_request("Under bins feature is not enabled")
             }
         }
         "/average_auction" => {
             if config.is_enabled(Feature::AverageAuction) {
-                averages(config, req, vec!["average_auction"]).await
+                averages(config, auth, req, vec!["average_auction"]).await
             } else {
                 bad_request("Average auction feature is not enabled")
             }
         }
         "/average_bin" => {
             if config.is_enabled(Feature::AverageBin) {
-                averages(config, req, vec!["average_bin"]).await
+                averages(config, auth, req, vec!["average_bin"]).await
             } else {
                 bad_request("Average bin feature is not enabled")
             }
@@ -137,21 +405,32 @@ async fn handle_response(
         "/average" => {
             if config.is_enabled(Feature::AverageAuction) && config.is_enabled(Feature::AverageBin)
             {
-                averages(config, req, vec!["average_bin", "average_auction"]).await
+                averages(config, auth, req, vec!["average_bin", "average_auction"]).await
             } else {
                 bad_request("Both average auction and average bin feature are not enabled")
             }
         }
+        "/history" => {
+            if config.is_enabled(Feature::AverageAuction) || config.is_enabled(Feature::AverageBin)
+            {
+                history(config, auth, req).await
+            } else {
+                bad_request("Both average auction and average bin feature are not enabled")
+            }
+        }
+        "/ingest_errors" => ingest_errors(auth).await,
+        "/api_keys/usage" => api_key_usage(config, auth, req).await,
+        "/metrics" => metrics(auth).await,
         "/debug" => {
             if config.debug {
-                debug_log(config, req).await
+                debug_log(auth).await
             } else {
                 bad_request("Debug is not enabled")
             }
         }
         "/info" => {
             if config.debug {
-                info_log(config, req).await
+                info_log(auth).await
             } else {
                 bad_request("Debug is not enabled")
             }
@@ -160,163 +439,422 @@ async fn handle_response(
     }
 }
 
-async fn debug_log(
-    config: Arc<Config>,
-    req: Request<impl Body>,
-) -> Result<Response<BoxBody<Bytes, Error>>, Error> {
-    let mut key = String::new();
+/* Route pattern of a path, so requests to paths with parameters are counted together */
+fn route_name(path: &str) -> &str {
+    match path {
+        "/" | "/query" | "/sales" | "/query_items" | "/pets" | "/lowestbin"
+        | "/lowestbin/history" | "/underbin" | "/average_auction" | "/average_bin" | "/average"
+        | "/history" | "/ingest_errors" | "/debug" | "/info" | "/api_keys" | "/api_keys/usage"
+        | "/metrics" => path,
+        path if path.starts_with("/lowestbin/") => "/lowestbin/{item_id}",
+        _ => "other",
+    }
+}
 
-    // Reads the query parameters from the request and stores them in the corresponding variable
-    for query_pair in Url::parse(&format!(
-        "http://{}{}",
-        config.full_url,
-        &req.uri().to_string()
-    ))
-    .unwrap()
-    .query_pairs()
-    {
-        if query_pair.0 == "key" {
-            key = query_pair.1.to_string();
+/* If handling a request to the path uses database connections */
+fn uses_database(config: &Config, auth: &Auth, path: &str) -> bool {
+    // Usage of keys from the api_keys table is stored in the database
+    auth.api_key.is_some()
+        || match route_name(path) {
+            "/query" | "/sales" | "/pets" | "/average_auction" | "/average_bin" | "/average"
+            | "/history" | "/lowestbin/history" | "/api_keys" | "/api_keys/usage" => true,
+            "/lowestbin" | "/lowestbin/{item_id}" | "/underbin" | "/query_items" => {
+                config.storage == StorageBackend::Postgres
+            }
+            _ => false,
         }
+}
+
+/* Keeps the database permit of a request until its body is sent, since streamed bodies use their connection until the last row */
+fn hold_permit(
+    response: Response<BoxBody<Bytes, BoxError>>,
+    permit: SemaphorePermit<'static>,
+) -> Response<BoxBody<Bytes, BoxError>> {
+    response.map(|body| {
+        body.map_frame(move |frame| {
+            let _permit = &permit;
+            frame
+        })
+        .boxed()
+    })
+}
+
+/* Gets the key of a request from the Authorization or X-API-Key header, else the deprecated key query parameter */
+fn request_key(config: &Config, req: &Request<impl Body>) -> String {
+    if let Some(key) = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|e| e.to_str().ok())
+        .and_then(|e| e.split_once(' '))
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("Bearer"))
+        .map(|(_, key)| key.trim())
+    {
+        return key.to_string();
+    }
+
+    if let Some(key) = req.headers().get("X-API-Key").and_then(|e| e.to_str().ok()) {
+        return key.trim().to_string();
+    }
+
+    Url::parse(&format!("http://{}{}", config.full_url, req.uri()))
+        .unwrap()
+        .query_pairs()
+        .find(|(name, _)| name == "key")
+        .map(|(_, value)| value.to_string())
+        .unwrap_or_default()
+}
+
+/* Checks that a key from the api_keys table has not expired, can use the endpoint, and has not reached its daily quota, then counts the request */
+async fn check_api_key(
+    auth: &Auth,
+    path: &str,
+) -> Result<Option<Response<BoxBody<Bytes, BoxError>>>, Error> {
+    // Other keys are checked by each endpoint
+    let Some(api_key) = &auth.api_key else {
+        return Ok(None);
+    };
+
+    let now = get_timestamp_millis();
+    if api_key.is_expired(now as i64) {
+        return http_err(StatusCode::UNAUTHORIZED, "API key has expired").map(Some);
+    }
+    if !api_key.allows(path) {
+        return http_err(StatusCode::FORBIDDEN, "API key cannot use this endpoint").map(Some);
+    }
+
+    match record_usage(api_key, route_name(path)).await {
+        Ok(true) => Ok(None),
+        Ok(false) => retry_later(
+            StatusCode::TOO_MANY_REQUESTS,
+            "API key has reached its daily quota",
+            seconds_until_quota_reset(now),
+        )
+        .map(Some),
+        Err(e) => internal_error(&format!("Error when recording API key usage: {}", e)).map(Some),
     }
+}
 
-    if !valid_api_key(config, key, true) {
+/* Lists the keys in the api_keys table */
+async fn api_keys(auth: &Auth) -> Result<Response<BoxBody<Bytes, BoxError>>, Error> {
+    if !auth.admin {
         return unauthorized();
     }
 
-    let file_result = fs::read("debug.log");
-    if file_result.is_err() {
-        return internal_error("Unable to open or read debug.log");
+    if DATABASE.lock().await.is_none() {
+        return bad_request("API keys require a database");
     }
 
-    Ok(Response::builder()
-        .status(StatusCode::OK)
-        .body(file_body(file_result))
-        .unwrap())
+    match get_client()
+        .await
+        .query("SELECT * FROM api_keys ORDER BY name", &[])
+        .await
+    {
+        Ok(rows) => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(json_body(
+                &rows.into_iter().map(ApiKey::from).collect::<Vec<ApiKey>>(),
+            ))
+            .unwrap()),
+        Err(e) => internal_error(&format!("Error when querying database: {}", e)),
+    }
+}
+
+/* Creates a key with a random value, which is only returned by this request */
+async fn create_api_key(
+    auth: &Auth,
+    req: Request<impl Body<Error = impl Into<BoxError>>>,
+) -> Result<Response<BoxBody<Bytes, BoxError>>, Error> {
+    let api_key_request = match read_api_key_request(auth, req).await {
+        Ok(api_key_request) => api_key_request,
+        Err(response) => return Ok(response),
+    };
+
+    let key = generate_key();
+    let result = get_client()
+        .await
+        .execute(
+            "INSERT INTO api_keys (name, key_hash, admin, endpoints, expires_at, daily_quota, created)
+                VALUES ($1, $2, $3, $4, $5, $6, $7) ON CONFLICT (name) DO NOTHING",
+            &[
+                &api_key_request.name,
+                &hash_key(&key),
+                &api_key_request.admin,
+                &api_key_request.endpoints,
+                &api_key_request.expires_at,
+                &api_key_request.daily_quota,
+                &(get_timestamp_millis() as i64),
+            ],
+        )
+        .await;
+
+    match result {
+        Ok(0) => bad_request(&format!(
+            "An API key named {} already exists",
+            api_key_request.name
+        )),
+        Ok(_) => {
+            if let Err(e) = refresh_api_keys().await {
+                return internal_error(&format!("Error when refreshing API keys: {}", e));
+            }
+            Ok(Response::builder()
+                .status(StatusCode::CREATED)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(json_body(&json!({
+                    "success": true,
+                    "name": api_key_request.name,
+                    "key": key
+                })))
+                .unwrap())
+        }
+        Err(e) => internal_error(&format!("Error when creating API key: {}", e)),
+    }
+}
+
+/* Replaces the settings of a key while keeping its value */
+async fn update_api_key(
+    auth: &Auth,
+    req: Request<impl Body<Error = impl Into<BoxError>>>,
+) -> Result<Response<BoxBody<Bytes, BoxError>>, Error> {
+    let api_key_request = match read_api_key_request(auth, req).await {
+        Ok(api_key_request) => api_key_request,
+        Err(response) => return Ok(response),
+    };
+
+    let result = get_client()
+        .await
+        .execute(
+            "UPDATE api_keys SET admin = $2, endpoints = $3, expires_at = $4, daily_quota = $5 WHERE name = $1",
+            &[
+                &api_key_request.name,
+                &api_key_request.admin,
+                &api_key_request.endpoints,
+                &api_key_request.expires_at,
+                &api_key_request.daily_quota,
+            ],
+        )
+        .await;
+
+    match result {
+        Ok(0) => bad_request(&format!("No API key named {}", api_key_request.name)),
+        Ok(_) => match refresh_api_keys().await {
+            Ok(_) => Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(json_body(&json!({"success": true})))
+                .unwrap()),
+            Err(e) => internal_error(&format!("Error when refreshing API keys: {}", e)),
+        },
+        Err(e) => internal_error(&format!("Error when updating API key: {}", e)),
+    }
+}
+
+/* Checks the admin key and reads the body of a request creating or updating a key */
+async fn read_api_key_request(
+    auth: &Auth,
+    req: Request<impl Body<Error = impl Into<BoxError>>>,
+) -> Result<ApiKeyRequest, Response<BoxBody<Bytes, BoxError>>> {
+    if !auth.admin {
+        return Err(unauthorized().unwrap());
+    }
+
+    if DATABASE.lock().await.is_none() {
+        return Err(bad_request("API keys require a database").unwrap());
+    }
+
+    let body = match Limited::new(req.into_body(), 65536).collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(e) => return Err(bad_request(&format!("Error reading body: {}", e)).unwrap()),
+    };
+    let api_key_request: ApiKeyRequest = match serde_json::from_slice(&body) {
+        Ok(api_key_request) => api_key_request,
+        Err(e) => return Err(bad_request(&format!("Error parsing body: {}", e)).unwrap()),
+    };
+
+    if api_key_request.name.is_empty() {
+        return Err(bad_request("The name is required").unwrap());
+    }
+    if let Some(endpoint) = api_key_request
+        .endpoints
+        .iter()
+        .find(|e| !e.starts_with('/'))
+    {
+        return Err(bad_request(&format!("Endpoint {} must start with /", endpoint)).unwrap());
+    }
+    if api_key_request.daily_quota.is_some_and(|e| e < 0) {
+        return Err(bad_request("The daily quota cannot be negative").unwrap());
+    }
+
+    Ok(api_key_request)
 }
 
-async fn info_log(
+/* Deletes a key, keeping its usage */
+async fn delete_api_key(
     config: Arc<Config>,
+    auth: &Auth,
     req: Request<impl Body>,
-) -> Result<Response<BoxBody<Bytes, Error>>, Error> {
-    let mut key = String::new();
+) -> Result<Response<BoxBody<Bytes, BoxError>>, Error> {
+    let mut name = String::new();
 
     // Reads the query parameters from the request and stores them in the corresponding variable
-    for query_pair in Url::parse(&format!(
-        "http://{}{}",
-        config.full_url,
-        &req.uri().to_string()
-    ))
-    .unwrap()
-    .query_pairs()
+    for query_pair in Url::parse(&format!("http://{}{}", config.full_url, &req.uri()))
+        .unwrap()
+        .query_pairs()
     {
-        if query_pair.0 == "key" {
-            key = query_pair.1.to_string();
+        if query_pair.0 == "name" {
+            name = query_pair.1.to_string();
         }
     }
 
-    if !valid_api_key(config, key, true) {
+    if !auth.admin {
         return unauthorized();
     }
 
-    let file_result = fs::read("info.log");
-    if file_result.is_err() {
-        return internal_error("Unable to open or read info.log");
+    if DATABASE.lock().await.is_none() {
+        return bad_request("API keys require a database");
     }
 
-    Ok(Response::builder()
-        .status(StatusCode::OK)
-        .body(file_body(file_result))
-        .unwrap())
+    if name.is_empty() {
+        return bad_request("The name parameter is required");
+    }
+
+    match get_client()
+        .await
+        .execute("DELETE FROM api_keys WHERE name = $1", &[&name])
+        .await
+    {
+        Ok(0) => bad_request(&format!("No API key named {}", name)),
+        Ok(_) => match refresh_api_keys().await {
+            Ok(_) => Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(json_body(&json!({"success": true})))
+                .unwrap()),
+            Err(e) => internal_error(&format!("Error when refreshing API keys: {}", e)),
+        },
+        Err(e) => internal_error(&format!("Error when deleting API key: {}", e)),
+    }
 }
 
-async fn pets(
+/* Gets the requests of each key to each endpoint for recent days */
+async fn api_key_usage(
     config: Arc<Config>,
+    auth: &Auth,
     req: Request<impl Body>,
-) -> Result<Response<BoxBody<Bytes, Error>>, Error> {
-    let mut query = String::new();
-    let mut key = String::new();
+) -> Result<Response<BoxBody<Bytes, BoxError>>, Error> {
+    let mut name = String::new();
+    let mut days = 7;
 
     // Reads the query parameters from the request and stores them in the corresponding variable
-    for query_pair in Url::parse(&format!(
-        "http://{}{}",
-        config.full_url,
-        &req.uri().to_string()
-    ))
-    .unwrap()
-    .query_pairs()
+    for query_pair in Url::parse(&format!("http://{}{}", config.full_url, &req.uri()))
+        .unwrap()
+        .query_pairs()
     {
-        if query_pair.0 == "query" {
-            query = query_pair.1.to_string();
-        } else if query_pair.0 == "key" {
-            key = query_pair.1.to_string();
+        match query_pair.0.to_string().as_str() {
+            "name" => name = query_pair.1.to_string(),
+            "days" => match query_pair.1.to_string().parse::<i32>() {
+                Ok(days_int) if days_int > 0 => days = days_int,
+                _ => return bad_request("Invalid days parameter"),
+            },
+            _ => {}
         }
     }
 
-    // The API key in request doesn't match
-    if !valid_api_key(config, key, false) {
+    if !auth.admin {
         return unauthorized();
     }
 
-    if query.is_empty() {
-        return bad_request("The query parameter cannot be empty");
+    if DATABASE.lock().await.is_none() {
+        return bad_request("API keys require a database");
     }
 
-    let mut sql: String = String::from("SELECT * FROM pets WHERE name IN (");
-    let mut param_vec: Vec<Box<String>> = Vec::new();
-    let mut param_count = 1;
+    let mut sql = String::from(
+        "SELECT name, day::TEXT AS day, endpoint, requests FROM api_key_usage WHERE day > (now() AT TIME ZONE 'UTC')::date - $1::INT",
+    );
+    let mut param_vec: Vec<&(dyn ToSql + Sync)> = vec![&days];
+    if !name.is_empty() {
+        sql.push_str(" AND name = $2");
+        param_vec.push(&name);
+    }
+    sql.push_str(" ORDER BY day DESC, requests DESC");
+
+    match get_client().await.query(&sql, &param_vec).await {
+        Ok(rows) => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(json_body(
+                &rows
+                    .into_iter()
+                    .map(ApiKeyUsage::from)
+                    .collect::<Vec<ApiKeyUsage>>(),
+            ))
+            .unwrap()),
+        Err(e) => internal_error(&format!("Error when querying database: {}", e)),
+    }
+}
 
-    let mut split = query.split(',');
-    for pet_name in split.by_ref() {
-        if param_count != 1 {
-            sql.push(',');
-        }
-        sql.push_str(&format!("${}", param_count));
-        param_vec.push(Box::new(pet_name.to_string()));
-        param_count += 1;
+async fn debug_log(auth: &Auth) -> Result<Response<BoxBody<Bytes, BoxError>>, Error> {
+    if !auth.admin {
+        return unauthorized();
     }
-    sql.push(')');
 
-    let out: &Vec<&String> = &param_vec
-        .iter()
-        .map(std::ops::Deref::deref)
-        .collect::<Vec<_>>();
+    let file_result = fs::read("debug.log");
+    if file_result.is_err() {
+        return internal_error("Unable to open or read debug.log");
+    }
 
-    // Find and sort using query JSON
-    let results_cursor = get_client().await.query_raw(&sql, out).await;
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .body(file_body(file_result))
+        .unwrap())
+}
 
-    if let Err(e) = results_cursor {
-        return internal_error(&format!("Error when querying database: {}", e));
+async fn info_log(auth: &Auth) -> Result<Response<BoxBody<Bytes, BoxError>>, Error> {
+    if !auth.admin {
+        return unauthorized();
     }
 
-    // Convert the cursor iterator to a vector
-    let results_vec: Vec<PetsDatabaseItem> = results_cursor
-        .unwrap()
-        .try_collect::<Vec<Row>>()
-        .await
-        .unwrap()
-        .into_iter()
-        .map(PetsDatabaseItem::from)
-        .collect();
+    let file_result = fs::read("info.log");
+    if file_result.is_err() {
+        return internal_error("Unable to open or read info.log");
+    }
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .body(file_body(file_result))
+        .unwrap())
+}
+
+async fn ingest_errors(auth: &Auth) -> Result<Response<BoxBody<Bytes, BoxError>>, Error> {
+    if !auth.admin {
+        return unauthorized();
+    }
 
-    // Return the vector of auctions serialized into JSON
+    // Return the auctions skipped during the last update serialized into JSON
     Ok(Response::builder()
         .status(StatusCode::OK)
         .header(header::CONTENT_TYPE, "application/json")
-        .body(json_body(&results_vec))
+        .body(json_body(&*INGEST_ERRORS.lock().await))
         .unwrap())
 }
 
-async fn averages(
+async fn pets(
     config: Arc<Config>,
+    auth: &Auth,
     req: Request<impl Body>,
-    tables: Vec<&str>,
-) -> Result<Response<BoxBody<Bytes, Error>>, Error> {
-    let mut key = String::new();
+) -> Result<Response<BoxBody<Bytes, BoxError>>, Error> {
+    let mut query = String::new();
+    let mut pet_type = String::new();
+    let mut tier = String::new();
+    let mut levels = String::new();
+    let mut tier_boost = Option::None;
+    let mut held_item = String::new();
+    let mut skin = String::new();
+    let mut candy_used = -1;
     let mut time = 0;
-    let mut step = 60;
     let mut center = String::from("mean");
     let mut percent = 0.25;
+    let mut format = String::new();
 
     // Reads the query parameters from the request and stores them in the corresponding variable
     for query_pair in Url::parse(&format!(
@@ -328,53 +866,245 @@ async fn averages(
     .query_pairs()
     {
         match query_pair.0.to_string().as_str() {
+            "query" => query = query_pair.1.to_string(),
+            "type" => pet_type = query_pair.1.to_string(),
+            "tier" => tier = query_pair.1.to_string(),
+            "levels" => levels = query_pair.1.to_string(),
+            "tier_boost" => match query_pair.1.to_string().parse::<bool>() {
+                Ok(tier_boost_bool) => tier_boost = Some(tier_boost_bool),
+                Err(e) => {
+                    return bad_request(&format!("Error parsing tier_boost parameter: {}", e))
+                }
+            },
+            "held_item" => held_item = query_pair.1.to_string(),
+            "skin" => skin = query_pair.1.to_string(),
+            "candy_used" => match query_pair.1.to_string().parse::<i16>() {
+                Ok(candy_used_int) => candy_used = candy_used_int,
+                Err(e) => {
+                    return bad_request(&format!("Error parsing candy_used parameter: {}", e))
+                }
+            },
             "time" => match query_pair.1.to_string().parse::<i32>() {
                 Ok(time_int) => time = time_int,
                 Err(e) => return bad_request(&format!("Error parsing time parameter: {}", e)),
             },
-            "step" => match query_pair.1.to_string().parse::<i32>() {
-                Ok(step_int) => step = step_int,
-                Err(e) => return bad_request(&format!("Error parsing step parameter: {}", e)),
-            },
-            "key" => key = query_pair.1.to_string(),
             "center" => center = query_pair.1.to_string(),
             "percent" => match query_pair.1.to_string().parse::<f32>() {
                 Ok(percent_float) => percent = percent_float,
                 Err(e) => return bad_request(&format!("Error parsing percent parameter: {}", e)),
             },
+            "format" => format = query_pair.1.to_string(),
             _ => {}
         }
     }
 
     // The API key in request doesn't match
-    if !valid_api_key(config, key, false) {
+    if !auth.valid {
         return unauthorized();
     }
 
+    let format = match request_format(&req, &format) {
+        Ok(format) => format,
+        Err(e) => return bad_request(&e),
+    };
+
     if time < 0 {
         return bad_request("The time parameter cannot be negative");
     }
 
-    if step <= 0 {
-        return bad_request("The step parameter must be positive");
-    }
-
-    if percent <= 0.0 || percent >= 1.0 {
-        return bad_request("The percent parameter must be between 0 and 1");
-    }
+    if !pet_type.is_empty() {
+        if tier.is_empty() {
+            return bad_request("The tier parameter is required when using the type parameter");
+        }
 
-    // Map each item id to its prices and sales
-    let avg_map: DashMap<String, AverageDatabaseItem> = DashMap::new();
+        let mut levels_vec = Vec::new();
+        for level in levels.split(',').filter(|s| !s.is_empty()) {
+            match level.trim().parse::<i16>() {
+                Ok(level_int) => levels_vec.push(level_int),
+                Err(e) => return bad_request(&format!("Error parsing levels parameter: {}", e)),
+            }
+        }
 
-    for table in tables {
-        // Find and sort using query JSON
-        let results_cursor = get_client()
-            .await
-            .query(
-                &format!("SELECT item_id, ARRAY_AGG((price, sales)::avg_ah) prices FROM {table} WHERE time_t > $1 GROUP BY item_id"),
-                &[&time],
-            )
-            .await;
+        let mut sql = String::from(
+            "SELECT level, AVG(price)::FLOAT8 AS price, COUNT(*) AS sales FROM pet_sales WHERE name = $1 AND tier = $2 AND end_t > $3",
+        );
+        let time_millis = time as i64 * 1000;
+        let pet_type = pet_type.to_uppercase();
+        let tier = tier.to_uppercase();
+        let mut param_vec: Vec<&(dyn ToSql + Sync)> = vec![&pet_type, &tier, &time_millis];
+        let mut param_count = 4;
+
+        param_count = bool_eq(
+            &mut sql,
+            &mut param_vec,
+            "tier_boost",
+            &tier_boost,
+            param_count,
+            false,
+        );
+        param_count = str_eq(
+            &mut sql,
+            &mut param_vec,
+            "held_item",
+            &held_item,
+            param_count,
+            false,
+        );
+        param_count = str_eq(&mut sql, &mut param_vec, "skin", &skin, param_count, false);
+        int_eq(
+            &mut sql,
+            &mut param_vec,
+            "candy_used",
+            &candy_used,
+            param_count,
+            false,
+        );
+        sql.push_str(" GROUP BY level ORDER BY level");
+
+        let results_cursor = get_client().await.query(&sql, &param_vec).await;
+
+        if let Err(e) = results_cursor {
+            return internal_error(&format!("Error when querying database: {}", e));
+        }
+
+        let sold_levels = results_cursor
+            .unwrap()
+            .into_iter()
+            .map(|row| (row.get("level"), row.get("price"), row.get("sales")))
+            .collect::<Vec<(i16, f64, i64)>>();
+
+        // Default to every level up to the highest sold level (e.g. golden dragons go to 200)
+        if levels_vec.is_empty() {
+            let max_level = sold_levels.last().map_or(100, |e| e.0.max(100));
+            levels_vec = (1..=max_level).collect();
+        }
+
+        return format_response(
+            format,
+            RowWriter::new(format, &["level", "price", "sales", "interpolated"])
+                .write_all(interpolate_pet_levels(&sold_levels, &levels_vec)),
+        );
+    }
+
+    if query.is_empty() {
+        return bad_request("The query parameter cannot be empty");
+    }
+
+    if percent <= 0.0 || percent >= 1.0 {
+        return bad_request("The percent parameter must be between 0 and 1");
+    }
+
+    let pet_names = query
+        .split(',')
+        .map(|s| s.to_string())
+        .collect::<Vec<String>>();
+
+    // Only use the prices of each update since the time
+    let database_ref = get_client().await;
+    let param_vec: Vec<&(dyn ToSql + Sync)> = vec![&time, &pet_names];
+    match database_ref
+        .query_raw(
+            "SELECT item_id, ARRAY_AGG((price, sales)::avg_ah) prices FROM average_pets WHERE time_t > $1 AND item_id = ANY($2) GROUP BY item_id",
+            param_vec,
+        )
+        .await
+    {
+        Ok(rows) => stream_response(
+            format,
+            &["name", "price", "count"],
+            StreamClient::new(database_ref, false),
+            rows,
+            move |row| {
+                let avg = AverageDatabaseItem::from(row);
+                PetsDatabaseItem {
+                    price: match center.as_str() {
+                        "median" => avg.get_median(),
+                        "modified_median" => avg.get_modified_median(percent),
+                        _ => avg.get_average(),
+                    } as i64,
+                    count: avg.get_sales(1.0) as i64,
+                    name: avg.item_id,
+                }
+            },
+        ),
+        Err(e) => internal_error(&format!("Error when querying database: {}", e)),
+    }
+}
+
+async fn averages(
+    config: Arc<Config>,
+    auth: &Auth,
+    req: Request<impl Body>,
+    tables: Vec<&str>,
+) -> Result<Response<BoxBody<Bytes, BoxError>>, Error> {
+    let mut time = 0;
+    let mut step = 60;
+    let mut center = String::from("mean");
+    let mut percent = 0.25;
+    let mut format = String::new();
+
+    // Reads the query parameters from the request and stores them in the corresponding variable
+    for query_pair in Url::parse(&format!(
+        "http://{}{}",
+        config.full_url,
+        &req.uri().to_string()
+    ))
+    .unwrap()
+    .query_pairs()
+    {
+        match query_pair.0.to_string().as_str() {
+            "time" => match query_pair.1.to_string().parse::<i32>() {
+                Ok(time_int) => time = time_int,
+                Err(e) => return bad_request(&format!("Error parsing time parameter: {}", e)),
+            },
+            "step" => match query_pair.1.to_string().parse::<i32>() {
+                Ok(step_int) => step = step_int,
+                Err(e) => return bad_request(&format!("Error parsing step parameter: {}", e)),
+            },
+            "center" => center = query_pair.1.to_string(),
+            "percent" => match query_pair.1.to_string().parse::<f32>() {
+                Ok(percent_float) => percent = percent_float,
+                Err(e) => return bad_request(&format!("Error parsing percent parameter: {}", e)),
+            },
+            "format" => format = query_pair.1.to_string(),
+            _ => {}
+        }
+    }
+
+    // The API key in request doesn't match
+    if !auth.valid {
+        return unauthorized();
+    }
+
+    let format = match request_format(&req, &format) {
+        Ok(format) => format,
+        Err(e) => return bad_request(&e),
+    };
+
+    if time < 0 {
+        return bad_request("The time parameter cannot be negative");
+    }
+
+    if step <= 0 {
+        return bad_request("The step parameter must be positive");
+    }
+
+    if percent <= 0.0 || percent >= 1.0 {
+        return bad_request("The percent parameter must be between 0 and 1");
+    }
+
+    // Map each item id to its prices and sales
+    let avg_map: DashMap<String, AverageDatabaseItem> = DashMap::new();
+
+    for table in tables {
+        // Find and sort using query JSON
+        let results_cursor = get_client()
+            .await
+            .query(
+                &format!("SELECT item_id, ARRAY_AGG((price, sales)::avg_ah) prices FROM {table} WHERE time_t > $1 GROUP BY item_id"),
+                &[&time],
+            )
+            .await;
 
         if let Err(e) = results_cursor {
             return internal_error(&format!("Error when querying database: {}", e));
@@ -409,53 +1139,22 @@ async fn averages(
         );
     }
 
-    // Return the vector of auctions or bins serialized into JSON
-    Ok(Response::builder()
-        .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, "application/json")
-        .body(json_body(&avg_map_final))
-        .unwrap())
+    // Return the map of auctions or bins serialized into the format
+    format_response(
+        format,
+        map_body(format, avg_map_final, &["item_id", "price", "sales"]),
+    )
 }
 
-async fn query(
+async fn history(
     config: Arc<Config>,
+    auth: &Auth,
     req: Request<impl Body>,
-) -> Result<Response<BoxBody<Bytes, Error>>, Error> {
-    let mut query = String::new();
-    let mut sort_by = String::new();
-    let mut sort_order = String::new();
-    let mut limit = 1;
-    let mut key = String::new();
-    let mut item_name = String::new();
-    let mut tier = String::new();
+) -> Result<Response<BoxBody<Bytes, BoxError>>, Error> {
     let mut item_id = String::new();
-    let mut internal_id = String::new();
-    let mut enchants = String::new();
-    let mut attributes = String::new();
-    let mut end = -1;
-    let mut bids = String::new();
+    let mut interval = String::from("1h");
+    let mut time = 0;
     let mut bin = Option::None;
-    let mut potato_books = -1;
-    let mut stars = -1;
-    let mut farming_for_dummies = -1;
-    let mut transmission_tuner = -1;
-    let mut mana_disintegrator = -1;
-    let mut reforge = String::new();
-    let mut rune = String::new();
-    let mut skin = String::new();
-    let mut power_scroll = String::new();
-    let mut drill_upgrade_module = String::new();
-    let mut drill_fuel_tank = String::new();
-    let mut drill_engine = String::new();
-    let mut dye = String::new();
-    let mut accessory_enrichment = String::new();
-    let mut recombobulated = Option::None;
-    let mut wood_singularity = Option::None;
-    let mut art_of_war = Option::None;
-    let mut art_of_peace = Option::None;
-    let mut etherwarp = Option::None;
-    let mut necron_scrolls = String::new();
-    let mut gemstones = String::new();
 
     // Reads the query parameters from the request and stores them in the corresponding variable
     for query_pair in Url::parse(&format!(
@@ -467,119 +1166,257 @@ async fn query(
     .query_pairs()
     {
         match query_pair.0.to_string().as_str() {
-            "query" => query = query_pair.1.to_string(),
-            "sort_by" => sort_by = query_pair.1.to_string(),
-            "sort_order" => sort_order = query_pair.1.to_string(),
-            "limit" => match query_pair.1.to_string().parse::<i64>() {
-                Ok(limit_int) => limit = limit_int,
-                Err(e) => return bad_request(&format!("Error parsing limit parameter: {}", e)),
-            },
-            "key" => key = query_pair.1.to_string(),
-            "item_name" => item_name = query_pair.1.to_string(),
-            "tier" => tier = query_pair.1.to_string(),
             "item_id" => item_id = query_pair.1.to_string(),
-            "internal_id" => internal_id = query_pair.1.to_string(),
-            "enchants" => enchants = query_pair.1.to_string(),
-            "attributes" => attributes = query_pair.1.to_string(),
-            "end" => match query_pair.1.to_string().parse::<i64>() {
-                Ok(end_int) => end = end_int,
-                Err(e) => return bad_request(&format!("Error parsing end parameter: {}", e)),
+            "interval" => interval = query_pair.1.to_string(),
+            "time" => match query_pair.1.to_string().parse::<i32>() {
+                Ok(time_int) => time = time_int,
+                Err(e) => return bad_request(&format!("Error parsing time parameter: {}", e)),
             },
-            "bids" => bids = query_pair.1.to_string(),
             "bin" => match query_pair.1.to_string().parse::<bool>() {
                 Ok(bin_bool) => bin = Some(bin_bool),
                 Err(e) => return bad_request(&format!("Error parsing bin parameter: {}", e)),
             },
-            "potato_books" => match query_pair.1.to_string().parse::<i16>() {
-                Ok(potato_books_int) => potato_books = potato_books_int,
-                Err(e) => {
-                    return bad_request(&format!("Error parsing potato_books parameter: {}", e))
-                }
-            },
-            "stars" => match query_pair.1.to_string().parse::<i16>() {
-                Ok(stars_int) => stars = stars_int,
-                Err(e) => return bad_request(&format!("Error parsing stars parameter: {}", e)),
-            },
-            "farming_for_dummies" => match query_pair.1.to_string().parse::<i16>() {
-                Ok(farming_for_dummies_int) => farming_for_dummies = farming_for_dummies_int,
-                Err(e) => {
-                    return bad_request(&format!(
-                        "Error parsing farming_for_dummies parameter: {}",
-                        e
-                    ))
-                }
-            },
-            "transmission_tuner" => match query_pair.1.to_string().parse::<i16>() {
-                Ok(transmission_tuner_int) => transmission_tuner = transmission_tuner_int,
-                Err(e) => {
-                    return bad_request(&format!(
-                        "Error parsing transmission_tuner parameter: {}",
-                        e
-                    ))
-                }
-            },
-            "mana_disintegrator" => match query_pair.1.to_string().parse::<i16>() {
-                Ok(mana_disintegrator_int) => mana_disintegrator = mana_disintegrator_int,
-                Err(e) => {
-                    return bad_request(&format!(
-                        "Error parsing mana_disintegrator parameter: {}",
-                        e
-                    ))
-                }
-            },
-            "reforge" => reforge = query_pair.1.to_string(),
-            "rune" => rune = query_pair.1.to_string(),
-            "skin" => skin = query_pair.1.to_string(),
-            "power_scroll" => power_scroll = query_pair.1.to_string(),
-            "drill_upgrade_module" => drill_upgrade_module = query_pair.1.to_string(),
-            "drill_fuel_tank" => drill_fuel_tank = query_pair.1.to_string(),
-            "drill_engine" => drill_engine = query_pair.1.to_string(),
-            "dye" => dye = query_pair.1.to_string(),
-            "accessory_enrichment" => accessory_enrichment = query_pair.1.to_string(),
-            "recombobulated" => match query_pair.1.to_string().parse::<bool>() {
-                Ok(recombobulated_bool) => recombobulated = Some(recombobulated_bool),
-                Err(e) => {
-                    return bad_request(&format!("Error parsing recombobulated parameter: {}", e))
-                }
-            },
-            "wood_singularity" => match query_pair.1.to_string().parse::<bool>() {
-                Ok(wood_singularity_bool) => wood_singularity = Some(wood_singularity_bool),
-                Err(e) => {
-                    return bad_request(&format!("Error parsing wood_singularity parameter: {}", e))
-                }
-            },
-            "art_of_war" => match query_pair.1.to_string().parse::<bool>() {
-                Ok(art_of_war_bool) => art_of_war = Some(art_of_war_bool),
-                Err(e) => {
-                    return bad_request(&format!("Error parsing art_of_war parameter: {}", e))
-                }
-            },
-            "art_of_peace" => match query_pair.1.to_string().parse::<bool>() {
-                Ok(art_of_peace_bool) => art_of_peace = Some(art_of_peace_bool),
-                Err(e) => {
-                    return bad_request(&format!("Error parsing art_of_peace parameter: {}", e))
-                }
-            },
-            "etherwarp" => match query_pair.1.to_string().parse::<bool>() {
-                Ok(etherwarp_bool) => etherwarp = Some(etherwarp_bool),
-                Err(e) => return bad_request(&format!("Error parsing etherwarp parameter: {}", e)),
-            },
-            "necron_scrolls" => necron_scrolls = query_pair.1.to_string(),
-            "gemstones" => gemstones = query_pair.1.to_string(),
             _ => {}
         }
     }
 
-    if !valid_api_key(config.clone(), key.to_owned(), false) {
+    // The API key in request doesn't match
+    if !auth.valid {
+        return unauthorized();
+    }
+
+    if item_id.is_empty() {
+        return bad_request("The item_id parameter is required");
+    }
+
+    if time < 0 {
+        return bad_request("The time parameter cannot be negative");
+    }
+
+    // Length of each candle in seconds
+    let interval_secs: i32 = match interval.as_str() {
+        "5m" => 300,
+        "1h" => 3600,
+        "1d" => 86400,
+        _ => return bad_request("The interval parameter must be '5m', '1h', or '1d'"),
+    };
+
+    // Bins, auctions, or both (if not specified)
+    let mut tables = Vec::new();
+    if bin != Some(false) && config.is_enabled(Feature::AverageBin) {
+        tables.push("average_bin");
+    }
+    if bin != Some(true) && config.is_enabled(Feature::AverageAuction) {
+        tables.push("average_auction");
+    }
+    if tables.is_empty() {
+        return bad_request(if bin == Some(true) {
+            "Average bin feature is not enabled"
+        } else {
+            "Average auction feature is not enabled"
+        });
+    }
+
+    // Each row is the average price and number of sales of one minute, where the source breaks ties between bins and auctions of the same minute
+    let prices_sql = tables
+        .iter()
+        .map(|table| {
+            format!("SELECT time_t, price, sales, '{table}' AS source FROM {table} WHERE item_id = $1 AND time_t > $2")
+        })
+        .collect::<Vec<String>>()
+        .join(" UNION ALL ");
+
+    let results_cursor = get_client()
+        .await
+        .query(
+            &format!(
+                "SELECT
+                    (time_t / $3) * $3 AS time,
+                    (ARRAY_AGG(price ORDER BY time_t ASC, source ASC))[1] AS open,
+                    MAX(price) AS high,
+                    MIN(price) AS low,
+                    (ARRAY_AGG(price ORDER BY time_t DESC, source DESC))[1] AS close,
+                    SUM(price * sales) / SUM(sales) AS mean,
+                    SUM(sales) AS volume
+                FROM ({prices_sql}) prices
+                GROUP BY 1
+                ORDER BY 1"
+            ),
+            &[&item_id, &time, &interval_secs],
+        )
+        .await;
+
+    if let Err(e) = results_cursor {
+        return internal_error(&format!("Error when querying database: {}", e));
+    }
+
+    let results_vec = results_cursor
+        .unwrap()
+        .into_iter()
+        .map(HistoryCandle::from)
+        .collect::<Vec<HistoryCandle>>();
+
+    // Return the vector of candles serialized into JSON
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(json_body(&results_vec))
+        .unwrap())
+}
+
+async fn query(
+    config: Arc<Config>,
+    auth: &Auth,
+    req: Request<impl Body>,
+) -> Result<Response<BoxBody<Bytes, BoxError>>, Error> {
+    let mut params = AuctionParams::default();
+    let mut query = String::new();
+    let mut cursor = Option::None;
+    let mut format = String::new();
+    let mut tier = String::new();
+    let mut bids = String::new();
+    let mut min_starting_bid: Option<i64> = None;
+    let mut max_starting_bid: Option<i64> = None;
+    let mut min_highest_bid: Option<i64> = None;
+    let mut max_highest_bid: Option<i64> = None;
+    let mut min_unit_price: Option<i64> = None;
+    let mut max_unit_price: Option<i64> = None;
+    let mut min_end_t: Option<i64> = None;
+    let mut max_end_t: Option<i64> = None;
+    let mut min_stars: Option<i16> = None;
+    let mut max_stars: Option<i16> = None;
+    let mut min_potato_books: Option<i16> = None;
+    let mut max_potato_books: Option<i16> = None;
+    let mut min_count: Option<i16> = None;
+    let mut max_count: Option<i16> = None;
+
+    // Reads the query parameters from the request and stores them in the corresponding variable
+    for query_pair in Url::parse(&format!(
+        "http://{}{}",
+        config.full_url,
+        &req.uri().to_string()
+    ))
+    .unwrap()
+    .query_pairs()
+    {
+        let (name, value) = (query_pair.0.as_ref(), query_pair.1.as_ref());
+        let parsed = match name {
+            "query" => {
+                query = value.to_string();
+                Ok(())
+            }
+            "cursor" => {
+                cursor = Some(value.to_string());
+                Ok(())
+            }
+            "format" => {
+                format = value.to_string();
+                Ok(())
+            }
+            "tier" => {
+                tier = value.to_string();
+                Ok(())
+            }
+            "bids" => {
+                bids = value.to_string();
+                Ok(())
+            }
+            "min_starting_bid" => parse_param(name, value).map(|e| min_starting_bid = Some(e)),
+            "max_starting_bid" => parse_param(name, value).map(|e| max_starting_bid = Some(e)),
+            "min_highest_bid" => parse_param(name, value).map(|e| min_highest_bid = Some(e)),
+            "max_highest_bid" => parse_param(name, value).map(|e| max_highest_bid = Some(e)),
+            "min_unit_price" => parse_param(name, value).map(|e| min_unit_price = Some(e)),
+            "max_unit_price" => parse_param(name, value).map(|e| max_unit_price = Some(e)),
+            "min_end_t" => parse_param(name, value).map(|e| min_end_t = Some(e)),
+            "max_end_t" => parse_param(name, value).map(|e| max_end_t = Some(e)),
+            "min_stars" => parse_param(name, value).map(|e| min_stars = Some(e)),
+            "max_stars" => parse_param(name, value).map(|e| max_stars = Some(e)),
+            "min_potato_books" => parse_param(name, value).map(|e| min_potato_books = Some(e)),
+            "max_potato_books" => parse_param(name, value).map(|e| max_potato_books = Some(e)),
+            "min_count" => parse_param(name, value).map(|e| min_count = Some(e)),
+            "max_count" => parse_param(name, value).map(|e| max_count = Some(e)),
+            _ => params.read(name, value),
+        };
+        if let Err(e) = parsed {
+            return bad_request(&e);
+        }
+    }
+    let AuctionParams {
+        ref sort_by,
+        ref sort_order,
+        limit,
+        ..
+    } = params;
+
+    if !auth.valid {
         return unauthorized();
     }
     // Prevent fetching too many rows
-    if (limit <= 0 || limit >= 500) && !valid_api_key(config.clone(), key.to_owned(), true) {
+    if !params.limit_allowed(auth) {
         return unauthorized();
     }
 
+    let format = match request_format(&req, &format) {
+        Ok(format) => format,
+        Err(e) => return bad_request(&e),
+    };
+    let mut columns = QUERY_COLUMNS.to_vec();
+    // Only the query sort computes a score, which admin queries do not use
+    if sort_by == "query" && query.is_empty() {
+        columns.insert(1, "score");
+    }
+
+    let sort_keys = if sort_by == "query" {
+        Vec::new()
+    } else {
+        match parse_sort(sort_by, sort_order, query_sort_column) {
+            Ok(sort_keys) => sort_keys,
+            Err(e) => return bad_request(&e),
+        }
+    };
+    let sort = if sort_keys.is_empty() {
+        String::from("uuid ASC")
+    } else {
+        sort_keys
+            .iter()
+            .map(|e| format!("{} {}", e.name, e.order))
+            .collect::<Vec<String>>()
+            .join(",")
+    };
+    if cursor.is_some() {
+        if !query.is_empty() || sort_by == "query" {
+            return bad_request("The cursor parameter cannot be used with query or sort_by=query");
+        }
+        if limit <= 0 {
+            return bad_request("The limit parameter must be positive when using a cursor");
+        }
+        if sort_keys.iter().any(|e| e.name == "stars") {
+            return bad_request("The cursor parameter cannot be used when sorting by stars");
+        }
+    }
+    // Position to continue from where an empty cursor starts at the first page
+    let page_cursor = match &cursor {
+        Some(cursor) if !cursor.is_empty() => match QueryCursor::decode(cursor) {
+            Some(page_cursor)
+                if page_cursor.sort == sort && page_cursor.values.len() == sort_keys.len() =>
+            {
+                page_cursor
+                    .values
+                    .iter()
+                    .zip(&sort_keys)
+                    .map(|(value, sort_key)| sort_key.to_param(value))
+                    .collect::<Option<Vec<FilterParam>>>()
+                    .map(|values| (values, page_cursor.uuid))
+            }
+            _ => None,
+        },
+        _ => None,
+    };
+    if cursor.as_ref().is_some_and(|e| !e.is_empty()) && page_cursor.is_none() {
+        return bad_request("Invalid cursor parameter");
+    }
+
     let database_ref = get_client().await;
-    let results_cursor;
 
     // Find and sort using query
     if query.is_empty() {
@@ -592,7 +1429,6 @@ async fn query(
 
         if !sort_by_query {
             if !bids.is_empty() {
-                // TODO: support bids in sort_by query
                 sql =
                     String::from("SELECT * FROM query, unnest(bids) AS bid WHERE bid.bidder = $1");
                 param_vec.push(&bids);
@@ -601,284 +1437,156 @@ async fn query(
                 sql = String::from("SELECT * FROM query WHERE");
             }
         }
-
-        param_count = int_eq(
+        param_count = int_cmp(
             &mut sql,
             &mut param_vec,
-            "stars",
-            &stars,
+            "starting_bid",
+            ">=",
+            &min_starting_bid,
             param_count,
             sort_by_query,
         );
-        param_count = int_eq(
+        param_count = int_cmp(
             &mut sql,
             &mut param_vec,
-            "potato_books",
-            &potato_books,
+            "starting_bid",
+            "<=",
+            &max_starting_bid,
             param_count,
             sort_by_query,
         );
-        param_count = int_eq(
+        param_count = int_cmp(
             &mut sql,
             &mut param_vec,
-            "farming_for_dummies",
-            &farming_for_dummies,
+            "highest_bid",
+            ">=",
+            &min_highest_bid,
             param_count,
             sort_by_query,
         );
-        param_count = int_eq(
+        param_count = int_cmp(
             &mut sql,
             &mut param_vec,
-            "transmission_tuner",
-            &transmission_tuner,
+            "highest_bid",
+            "<=",
+            &max_highest_bid,
             param_count,
             sort_by_query,
         );
-        param_count = int_eq(
+        param_count = int_cmp(
             &mut sql,
             &mut param_vec,
-            "mana_disintegrator",
-            &mana_disintegrator,
+            "(GREATEST(starting_bid, highest_bid) / GREATEST(count, 1))",
+            ">=",
+            &min_unit_price,
             param_count,
             sort_by_query,
         );
-
-        param_count = str_eq(
+        param_count = int_cmp(
             &mut sql,
             &mut param_vec,
-            "reforge",
-            &reforge,
+            "(GREATEST(starting_bid, highest_bid) / GREATEST(count, 1))",
+            "<=",
+            &max_unit_price,
             param_count,
             sort_by_query,
         );
-        param_count = str_eq(
+        param_count = int_cmp(
             &mut sql,
             &mut param_vec,
-            "rune",
-            &rune,
+            "end_t",
+            ">=",
+            &min_end_t,
             param_count,
             sort_by_query,
         );
-        param_count = str_eq(
+        param_count = int_cmp(
             &mut sql,
             &mut param_vec,
-            "skin",
-            &skin,
+            "end_t",
+            "<=",
+            &max_end_t,
             param_count,
             sort_by_query,
         );
-        param_count = str_eq(
+        param_count = int_cmp(
             &mut sql,
             &mut param_vec,
-            "tier",
-            &tier,
-            param_count,
-            sort_by_query,
-        );
-        param_count = str_eq(
-            &mut sql,
-            &mut param_vec,
-            "dye",
-            &dye,
+            "stars",
+            ">=",
+            &min_stars,
             param_count,
             sort_by_query,
         );
-        param_count = str_eq(
+        param_count = int_cmp(
             &mut sql,
             &mut param_vec,
-            "internal_id",
-            &internal_id,
+            "stars",
+            "<=",
+            &max_stars,
             param_count,
             sort_by_query,
         );
-        param_count = str_eq(
+        param_count = int_cmp(
             &mut sql,
             &mut param_vec,
-            "power_scroll",
-            &power_scroll,
+            "potato_books",
+            ">=",
+            &min_potato_books,
             param_count,
             sort_by_query,
         );
-        param_count = str_eq(
+        param_count = int_cmp(
             &mut sql,
             &mut param_vec,
-            "drill_upgrade_module",
-            &drill_upgrade_module,
+            "potato_books",
+            "<=",
+            &max_potato_books,
             param_count,
             sort_by_query,
         );
-        param_count = str_eq(
+        param_count = int_cmp(
             &mut sql,
             &mut param_vec,
-            "drill_fuel_tank",
-            &drill_fuel_tank,
+            "count",
+            ">=",
+            &min_count,
             param_count,
             sort_by_query,
         );
-        param_count = str_eq(
+        param_count = int_cmp(
             &mut sql,
             &mut param_vec,
-            "drill_engine",
-            &drill_engine,
+            "count",
+            "<=",
+            &max_count,
             param_count,
             sort_by_query,
         );
         param_count = str_eq(
             &mut sql,
             &mut param_vec,
-            "accessory_enrichment",
-            &accessory_enrichment,
-            param_count,
-            sort_by_query,
-        );
-
-        param_count = bool_eq(
-            &mut sql,
-            &mut param_vec,
-            "bin",
-            &bin,
-            param_count,
-            sort_by_query,
-        );
-        param_count = bool_eq(
-            &mut sql,
-            &mut param_vec,
-            "recombobulated",
-            &recombobulated,
-            param_count,
-            sort_by_query,
-        );
-        param_count = bool_eq(
-            &mut sql,
-            &mut param_vec,
-            "wood_singularity",
-            &wood_singularity,
-            param_count,
-            sort_by_query,
-        );
-        param_count = bool_eq(
-            &mut sql,
-            &mut param_vec,
-            "art_of_war",
-            &art_of_war,
-            param_count,
-            sort_by_query,
-        );
-        param_count = bool_eq(
-            &mut sql,
-            &mut param_vec,
-            "art_of_peace",
-            &art_of_peace,
+            "tier",
+            &tier,
             param_count,
             sort_by_query,
         );
-        param_count = bool_eq(
+        param_count = params.push_filters(
             &mut sql,
+            &mut sort_by_query_end_sql,
             &mut param_vec,
-            "etherwarp",
-            &etherwarp,
             param_count,
             sort_by_query,
         );
-
-        let enchants_split: Vec<String>;
-        if !enchants.is_empty() {
-            enchants_split = enchants.split(',').map(|s| s.trim().to_string()).collect();
-            param_count = array_contains(
-                &mut sql,
-                &mut param_vec,
-                "enchants",
-                &enchants_split,
-                param_count,
-                sort_by_query,
-            );
-        }
-        let attributes_split: Vec<String>;
-        if !attributes.is_empty() {
-            attributes_split = attributes
-                .split(',')
-                .map(|s| s.trim().to_string())
-                .collect();
-            param_count = array_contains(
-                &mut sql,
-                &mut param_vec,
-                "attributes",
-                &attributes_split,
-                param_count,
-                sort_by_query,
-            );
-        }
-        let necron_scrolls_split: Vec<String>;
-        if !necron_scrolls.is_empty() {
-            necron_scrolls_split = necron_scrolls
-                .split(',')
-                .map(|s| s.trim().to_string())
-                .collect();
-            param_count = array_contains(
-                &mut sql,
-                &mut param_vec,
-                "necron_scrolls",
-                &necron_scrolls_split,
-                param_count,
-                sort_by_query,
-            );
-        }
-        let gemstones_split: Vec<String>;
-        if !gemstones.is_empty() {
-            gemstones_split = gemstones.split(',').map(|s| s.trim().to_string()).collect();
-            param_count = array_contains(
-                &mut sql,
-                &mut param_vec,
-                "gemstones",
-                &gemstones_split,
-                param_count,
-                sort_by_query,
-            );
-        }
-
-        if !item_id.is_empty() {
-            if sort_by_query {
-                if !sort_by_query_end_sql.is_empty() {
-                    sort_by_query_end_sql.push_str(" AND");
-                }
-                sort_by_query_end_sql.push_str(&format!(" item_id = ${}", param_count));
-            } else {
-                if param_count != 1 {
-                    sql.push_str(" AND");
-                }
-                sql.push_str(&format!(" item_id = ${}", param_count));
-            }
-            param_vec.push(&item_id);
-            param_count += 1;
-        }
-        if end >= 0 {
-            if sort_by_query {
-                if !sort_by_query_end_sql.is_empty() {
-                    sort_by_query_end_sql.push_str(" AND");
-                }
-                sort_by_query_end_sql.push_str(&format!(" end_t > ${}", param_count));
-            } else {
-                if param_count != 1 {
-                    sql.push_str(" AND");
-                }
-                sql.push_str(&format!(" end_t > ${}", param_count));
-            }
-            param_vec.push(&end);
-            param_count += 1;
-        }
-        if !item_name.is_empty() {
-            if sort_by_query {
-                if !sort_by_query_end_sql.is_empty() {
-                    sort_by_query_end_sql.push_str(" AND");
-                }
-                sort_by_query_end_sql.push_str(&format!(" item_name ILIKE ${}", param_count));
-            } else {
-                if param_count != 1 {
-                    sql.push_str(" AND");
-                }
-                sql.push_str(&format!(" item_name ILIKE ${}", param_count));
+        if sort_by_query && !bids.is_empty() {
+            if !sort_by_query_end_sql.is_empty() {
+                sort_by_query_end_sql.push_str(" AND");
             }
-            param_vec.push(&item_name);
+            sort_by_query_end_sql.push_str(&format!(
+                " EXISTS (SELECT FROM unnest(bids) AS bid WHERE bid.bidder = ${})",
+                param_count
+            ));
+            param_vec.push(&bids);
             param_count += 1;
         }
 
@@ -889,14 +1597,44 @@ async fn query(
             sql.push_str(" 1=1");
         }
 
+        if let Some((values, uuid)) = &page_cursor {
+            // Rows after the cursor in the sort order, such as (a > $1) OR (a = $1 AND uuid > $2)
+            let mut after = Vec::new();
+            let mut equal = String::new();
+            for (value, sort_key) in values.iter().zip(&sort_keys) {
+                after.push(format!(
+                    "({}{} {} ${})",
+                    equal,
+                    sort_key.column,
+                    if sort_key.order == "DESC" { "<" } else { ">" },
+                    param_count
+                ));
+                equal.push_str(&format!("{} = ${} AND ", sort_key.column, param_count));
+                param_vec.push(value.as_ref());
+                param_count += 1;
+            }
+            after.push(format!("({}uuid > ${})", equal, param_count));
+            param_vec.push(uuid);
+            param_count += 1;
+            sql.push_str(&format!(" AND ({})", after.join(" OR ")));
+        }
+
         if sort_by_query {
             sort_by_query_end_sql.push_str(" ORDER BY score DESC, cur_bid");
-        } else if (sort_by == "starting_bid" || sort_by == "highest_bid")
-            && (sort_order == "ASC" || sort_order == "DESC")
-        {
-            sql.push_str(&format!(" ORDER BY {} {}", sort_by, sort_order));
+        } else if !sort_keys.is_empty() {
+            sql.push_str(&format!(" ORDER BY {}", order_by(&sort_keys)));
+            if cursor.is_some() {
+                // Break ties so every auction is on exactly one page
+                sql.push_str(", uuid ASC");
+            }
+        } else if cursor.is_some() {
+            sql.push_str(" ORDER BY uuid ASC");
         };
 
+        // The last auction of the page and the one after it, if there is a next page
+        let last_sql = format!("{} LIMIT 2 OFFSET {}", sql, limit - 1);
+        let last_param_vec = param_vec.clone();
+
         if limit > 0 {
             if sort_by_query {
                 sort_by_query_end_sql.push_str(&format!(" LIMIT ${}", param_count));
@@ -914,17 +1652,220 @@ async fn query(
             );
         }
 
-        results_cursor = database_ref.query(&sql, &param_vec).await;
-    } else {
-        if !valid_api_key(config, key, true) {
-            return unauthorized();
-        }
+        if cursor.is_none() {
+            return match database_ref.query_raw(&sql, param_vec).await {
+                Ok(rows) => stream_response(
+                    format,
+                    &columns,
+                    StreamClient::new(database_ref, false),
+                    rows,
+                    QueryDatabaseItem::from,
+                ),
+                Err(e) => internal_error(&format!("Error when querying database: {}", e)),
+            };
+        }
+
+        // Both queries read the same snapshot so the next cursor is after the last streamed auction
+        let database = StreamClient::new(database_ref, true);
+        let results = async {
+            database
+                .client()
+                .batch_execute("BEGIN ISOLATION LEVEL REPEATABLE READ READ ONLY")
+                .await?;
+            let last_rows = database.client().query(&last_sql, &last_param_vec).await?;
+            let rows = database.client().query_raw(&sql, param_vec).await?;
+            Ok::<_, tokio_postgres::Error>((last_rows, rows))
+        }
+        .await;
+
+        match results {
+            Ok((last_rows, rows)) => {
+                let next_cursor = if last_rows.len() > 1 {
+                    let last = QueryDatabaseItem::from(last_rows.into_iter().next().unwrap());
+                    Some(
+                        QueryCursor {
+                            sort,
+                            values: sort_keys.iter().map(|e| e.value(&last)).collect(),
+                            uuid: last.uuid,
+                        }
+                        .encode(),
+                    )
+                } else {
+                    None
+                };
+                stream_page(format, &columns, database, rows, next_cursor)
+            }
+            Err(e) => internal_error(&format!("Error when querying database: {}", e)),
+        }
+    } else {
+        if !auth.admin {
+            return unauthorized();
+        }
+
+        let mut params: Vec<FilterParam> = Vec::new();
+        let mut sql = if config.raw_sql_query {
+            // Parenthesized so the condition cannot extend past the sort and limit
+            format!("SELECT * FROM query WHERE ({})", query)
+        } else {
+            match parse_filter(&query).and_then(|e| e.to_sql(&mut params)) {
+                Ok(filter_sql) => format!("SELECT * FROM query WHERE {}", filter_sql),
+                Err(e) => return bad_request(&format!("Error parsing query parameter: {}", e)),
+            }
+        };
+        if !sort_keys.is_empty() {
+            sql.push_str(&format!(" ORDER BY {}", order_by(&sort_keys)));
+        }
+        if limit > 0 {
+            params.push(Box::new(limit));
+            sql.push_str(&format!(" LIMIT ${}", params.len()));
+        }
+
+        let param_vec = params
+            .iter()
+            .map(|e| e.as_ref() as &(dyn ToSql + Sync))
+            .collect::<Vec<&(dyn ToSql + Sync)>>();
+        let database = StreamClient::new(database_ref, true);
+        match admin_query(&config, &database, &auth.name, &query, &sql, &param_vec).await {
+            Ok(rows) => stream_response(format, &columns, database, rows, QueryDatabaseItem::from),
+            Err(e) => internal_error(&format!("Error when querying database: {}", e)),
+        }
+    }
+}
+
+async fn query_post(
+    config: Arc<Config>,
+    auth: &Auth,
+    req: Request<impl Body<Error = impl Into<BoxError>>>,
+) -> Result<Response<BoxBody<Bytes, BoxError>>, Error> {
+    let mut format = String::new();
+
+    // Reads the query parameters from the request and stores them in the corresponding variable
+    for query_pair in Url::parse(&format!("http://{}{}", config.full_url, &req.uri()))
+        .unwrap()
+        .query_pairs()
+    {
+        if query_pair.0 == "format" {
+            format = query_pair.1.to_string();
+        }
+    }
+
+    if !auth.valid {
+        return unauthorized();
+    }
+
+    let format = match request_format(&req, &format) {
+        Ok(format) => format,
+        Err(e) => return bad_request(&e),
+    };
+
+    let body = match Limited::new(req.into_body(), 65536).collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(e) => return bad_request(&format!("Error reading body: {}", e)),
+    };
+    let query_request: QueryRequest = match serde_json::from_slice(&body) {
+        Ok(query_request) => query_request,
+        Err(e) => return bad_request(&format!("Error parsing body: {}", e)),
+    };
+
+    // Prevent fetching too many rows
+    if (query_request.limit <= 0 || query_request.limit > MAX_LIMIT) && !auth.admin {
+        return unauthorized();
+    }
+
+    let mut params: Vec<FilterParam> = Vec::new();
+    let mut sql = match query_request.filter.to_sql(&mut params) {
+        Ok(filter_sql) => format!("SELECT * FROM query WHERE {}", filter_sql),
+        Err(e) => return bad_request(&e),
+    };
+
+    match parse_sort(
+        &query_request.sort_by,
+        &query_request.sort_order,
+        query_sort_column,
+    ) {
+        Ok(sort_keys) if !sort_keys.is_empty() => {
+            sql.push_str(&format!(" ORDER BY {}", order_by(&sort_keys)))
+        }
+        Ok(_) => {}
+        Err(e) => return bad_request(&e),
+    }
+
+    if query_request.limit > 0 {
+        params.push(Box::new(query_request.limit));
+        sql.push_str(&format!(" LIMIT ${}", params.len()));
+    }
+
+    let param_vec = params
+        .iter()
+        .map(|e| e.as_ref() as &(dyn ToSql + Sync))
+        .collect::<Vec<&(dyn ToSql + Sync)>>();
+    // Filters can be combined into slow queries, so they are limited like admin queries
+    let database = StreamClient::new(get_client().await, true);
+    match read_only_query(&config, &database, &sql, &param_vec).await {
+        Ok(rows) => stream_response(
+            format,
+            &QUERY_COLUMNS,
+            database,
+            rows,
+            QueryDatabaseItem::from,
+        ),
+        Err(e) => internal_error(&format!("Error when querying database: {}", e)),
+    }
+}
+
+async fn sales(
+    config: Arc<Config>,
+    auth: &Auth,
+    req: Request<impl Body>,
+) -> Result<Response<BoxBody<Bytes, BoxError>>, Error> {
+    let mut params = AuctionParams::default();
+
+    // Reads the query parameters from the request and stores them in the corresponding variable
+    for query_pair in Url::parse(&format!(
+        "http://{}{}",
+        config.full_url,
+        &req.uri().to_string()
+    ))
+    .unwrap()
+    .query_pairs()
+    {
+        if let Err(e) = params.read(&query_pair.0, &query_pair.1) {
+            return bad_request(&e);
+        }
+    }
+
+    if !auth.valid {
+        return unauthorized();
+    }
+    // Prevent fetching too many rows
+    if !params.limit_allowed(auth) {
+        return unauthorized();
+    }
 
-        results_cursor = database_ref
-            .query(&format!("SELECT * FROM query WHERE {}", query), &[])
-            .await;
+    let mut sql = String::from("SELECT * FROM ended_auctions WHERE");
+    let mut param_vec: Vec<&(dyn ToSql + Sync)> = Vec::new();
+    let param_count = params.push_filters(&mut sql, &mut String::new(), &mut param_vec, 1, false);
+
+    // Handle unfinished WHERE
+    if param_count == 1 {
+        sql.push_str(" 1=1");
+    }
+
+    let sort_keys = match parse_sort(&params.sort_by, &params.sort_order, sales_sort_column) {
+        Ok(sort_keys) => sort_keys,
+        Err(e) => return bad_request(&e),
+    };
+    if !sort_keys.is_empty() {
+        sql.push_str(&format!(" ORDER BY {}", order_by(&sort_keys)));
+    }
+
+    if params.limit > 0 {
+        sql.push_str(&format!(" LIMIT ${}", param_count));
+        param_vec.push(&params.limit);
     }
 
+    let results_cursor = get_client().await.query(&sql, &param_vec).await;
+
     if let Err(e) = results_cursor {
         return internal_error(&format!("Error when querying database: {}", e));
     }
@@ -933,10 +1874,10 @@ async fn query(
     let results_vec = results_cursor
         .unwrap()
         .into_iter()
-        .map(QueryDatabaseItem::from)
-        .collect::<Vec<QueryDatabaseItem>>();
+        .map(SalesDatabaseItem::from)
+        .collect::<Vec<SalesDatabaseItem>>();
 
-    // Return the vector of auctions serialized into JSON
+    // Return the vector of sales serialized into JSON
     Ok(Response::builder()
         .status(StatusCode::OK)
         .header(header::CONTENT_TYPE, "application/json")
@@ -946,105 +1887,343 @@ async fn query(
 
 async fn query_items(
     config: Arc<Config>,
+    auth: &Auth,
+    req: Request<impl Body>,
+) -> Result<Response<BoxBody<Bytes, BoxError>>, Error> {
+    if !auth.valid {
+        return unauthorized();
+    }
+
+    snapshot_response(
+        config.storage,
+        Dataset::QueryItems,
+        &req,
+        Format::Json,
+        |data| Ok(Some(data)),
+    )
+    .await
+}
+
+async fn lowestbin(
+    config: Arc<Config>,
+    auth: &Auth,
     req: Request<impl Body>,
-) -> Result<Response<BoxBody<Bytes, Error>>, Error> {
-    let mut key = String::new();
+) -> Result<Response<BoxBody<Bytes, BoxError>>, Error> {
+    let mut ids = String::new();
+    let mut prefix = String::new();
+    let mut regex = String::new();
+    let mut min_price = Option::None;
+    let mut max_price = Option::None;
+    let mut format = String::new();
 
     // Reads the query parameters from the request and stores them in the corresponding variable
-    for query_pair in Url::parse(&format!(
-        "http://{}{}",
-        config.full_url,
-        &req.uri().to_string()
-    ))
-    .unwrap()
-    .query_pairs()
+    for query_pair in Url::parse(&format!("http://{}{}", config.full_url, &req.uri()))
+        .unwrap()
+        .query_pairs()
     {
-        if query_pair.0 == "key" {
-            key = query_pair.1.to_string();
+        match query_pair.0.to_string().as_str() {
+            "ids" => ids = query_pair.1.to_string(),
+            "prefix" => prefix = query_pair.1.to_string(),
+            "regex" => regex = query_pair.1.to_string(),
+            "min_price" => match query_pair.1.to_string().parse::<f32>() {
+                Ok(min_price_float) => min_price = Some(min_price_float),
+                Err(e) => return bad_request(&format!("Error parsing min_price parameter: {}", e)),
+            },
+            "max_price" => match query_pair.1.to_string().parse::<f32>() {
+                Ok(max_price_float) => max_price = Some(max_price_float),
+                Err(e) => return bad_request(&format!("Error parsing max_price parameter: {}", e)),
+            },
+            "format" => format = query_pair.1.to_string(),
+            _ => {}
         }
     }
 
-    if !valid_api_key(config, key, false) {
+    if !auth.valid {
         return unauthorized();
     }
 
-    let file_result = fs::read("query_items.json");
-    if file_result.is_err() {
-        return internal_error("Unable to open or read query_items.json");
+    let format = match request_format(&req, &format) {
+        Ok(format) => format,
+        Err(e) => return bad_request(&e),
+    };
+
+    // Return the full map if there are no filters
+    if format == Format::Json
+        && ids.is_empty()
+        && prefix.is_empty()
+        && regex.is_empty()
+        && min_price.is_none()
+        && max_price.is_none()
+    {
+        return snapshot_response(config.storage, Dataset::Lowestbin, &req, format, |data| {
+            Ok(Some(data))
+        })
+        .await;
     }
 
-    Ok(Response::builder()
-        .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, "application/json")
-        .body(file_body(file_result))
-        .unwrap())
+    let ids_split = ids
+        .split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .collect::<HashSet<&str>>();
+    let regex = if regex.is_empty() {
+        None
+    } else {
+        match Regex::new(&regex) {
+            Ok(regex) => Some(regex),
+            Err(e) => return bad_request(&format!("Error parsing regex parameter: {}", e)),
+        }
+    };
+
+    snapshot_response(config.storage, Dataset::Lowestbin, &req, format, |data| {
+        let prices: HashMap<String, f32> = serde_json::from_slice(&data)?;
+        let filtered = prices
+            .into_iter()
+            .filter(|(id, price)| {
+                (ids_split.is_empty() || ids_split.contains(id.as_str()))
+                    && id.starts_with(&prefix)
+                    && regex.as_ref().map_or(true, |r| r.is_match(id))
+                    && min_price.map_or(true, |min| *price >= min)
+                    && max_price.map_or(true, |max| *price <= max)
+            })
+            .collect::<HashMap<String, f32>>();
+        Ok(Some(map_body(format, filtered, &["id", "price"])))
+    })
+    .await
 }
 
-async fn lowestbin(
+async fn lowestbin_item(
+    config: Arc<Config>,
+    auth: &Auth,
+    req: Request<impl Body>,
+) -> Result<Response<BoxBody<Bytes, BoxError>>, Error> {
+    if !auth.valid {
+        return unauthorized();
+    }
+
+    // Internal ids can contain characters such as ';' and '+', so the path may be percent encoded
+    let id = percent_decode_str(req.uri().path().trim_start_matches("/lowestbin/"))
+        .decode_utf8_lossy()
+        .to_string();
+
+    snapshot_response(
+        config.storage,
+        Dataset::LowestbinDetails,
+        &req,
+        Format::Json,
+        |data| {
+            let mut details: HashMap<String, LowestBin> = serde_json::from_slice(&data)?;
+            details
+                .remove(&id)
+                .map(|lowest_bin| {
+                    serde_json::to_vec(&json!({
+                        "id": id,
+                        "price": lowest_bin.price,
+                        "uuid": lowest_bin.uuid,
+                        "auctioneer": lowest_bin.auctioneer,
+                    }))
+                    .map(Bytes::from)
+                })
+                .transpose()
+        },
+    )
+    .await
+}
+
+async fn lowestbin_history(
     config: Arc<Config>,
+    auth: &Auth,
     req: Request<impl Body>,
-) -> Result<Response<BoxBody<Bytes, Error>>, Error> {
-    let mut key = String::new();
+) -> Result<Response<BoxBody<Bytes, BoxError>>, Error> {
+    let mut id = String::new();
+    let mut from = 0;
+    let mut to = i64::MAX;
 
     // Reads the query parameters from the request and stores them in the corresponding variable
     for query_pair in Url::parse(&format!("http://{}{}", config.full_url, &req.uri()))
         .unwrap()
         .query_pairs()
     {
-        if query_pair.0 == "key" {
-            key = query_pair.1.to_string();
+        match query_pair.0.to_string().as_str() {
+            "id" => id = query_pair.1.to_string(),
+            "from" => match query_pair.1.to_string().parse::<i64>() {
+                Ok(from_int) => from = from_int,
+                Err(e) => return bad_request(&format!("Error parsing from parameter: {}", e)),
+            },
+            "to" => match query_pair.1.to_string().parse::<i64>() {
+                Ok(to_int) => to = to_int,
+                Err(e) => return bad_request(&format!("Error parsing to parameter: {}", e)),
+            },
+            _ => {}
         }
     }
 
-    if !valid_api_key(config, key, false) {
+    if !auth.valid {
         return unauthorized();
     }
 
-    let file_result = fs::read("lowestbin.json");
-    if file_result.is_err() {
-        return internal_error("Unable to open or read lowestbin.json");
+    if id.is_empty() {
+        return bad_request("The id parameter is required");
     }
 
+    let results_cursor = get_client()
+        .await
+        .query(
+            "SELECT time_t, price FROM lowestbin_history WHERE internal_id = $1 AND time_t >= $2 AND time_t <= $3 ORDER BY time_t",
+            &[&id, &from, &to],
+        )
+        .await;
+
+    if let Err(e) = results_cursor {
+        return internal_error(&format!("Error when querying database: {}", e));
+    }
+
+    let results_vec = results_cursor
+        .unwrap()
+        .into_iter()
+        .map(LowestbinHistoryItem::from)
+        .collect::<Vec<LowestbinHistoryItem>>();
+
+    // Return the vector of lowest bin changes serialized into JSON
     Ok(Response::builder()
         .status(StatusCode::OK)
         .header(header::CONTENT_TYPE, "application/json")
-        .body(file_body(file_result))
+        .body(json_body(&results_vec))
         .unwrap())
 }
 
 async fn underbin(
     config: Arc<Config>,
+    auth: &Auth,
     req: Request<impl Body>,
-) -> Result<Response<BoxBody<Bytes, Error>>, Error> {
-    let mut key = String::new();
+) -> Result<Response<BoxBody<Bytes, BoxError>>, Error> {
+    let mut format = String::new();
 
     // Reads the query parameters from the request and stores them in the corresponding variable
     for query_pair in Url::parse(&format!("http://{}{}", config.full_url, &req.uri()))
         .unwrap()
         .query_pairs()
     {
-        if query_pair.0 == "key" {
-            key = query_pair.1.to_string();
+        if query_pair.0 == "format" {
+            format = query_pair.1.to_string();
         }
     }
 
-    if !valid_api_key(config, key, false) {
+    if !auth.valid {
         return unauthorized();
     }
 
-    let file_result = fs::read("underbin.json");
-    if file_result.is_err() {
-        return internal_error("Unable to open or read underbin.json");
+    let format = match request_format(&req, &format) {
+        Ok(format) => format,
+        Err(e) => return bad_request(&e),
+    };
+
+    snapshot_response(config.storage, Dataset::Underbin, &req, format, |data| {
+        if format == Format::Json {
+            return Ok(Some(data));
+        }
+
+        let under_bins: HashMap<String, Value> = serde_json::from_slice(&data)?;
+        Ok(Some(map_body(
+            format,
+            under_bins,
+            &[
+                "uuid",
+                "name",
+                "id",
+                "auctioneer",
+                "starting_bid",
+                "past_bin_price",
+                "profit",
+            ],
+        )))
+    })
+    .await
+}
+
+/* Responds with the latest snapshot of the dataset (passed through the filter, which serializes it into the format) or 304 if the client already has it */
+async fn snapshot_response(
+    storage: StorageBackend,
+    dataset: Dataset,
+    req: &Request<impl Body>,
+    format: Format,
+    filter: impl FnOnce(Bytes) -> Result<Option<Bytes>, serde_json::Error>,
+) -> Result<Response<BoxBody<Bytes, BoxError>>, Error> {
+    let encoding = Encoding::negotiate(
+        req.headers()
+            .get(header::ACCEPT_ENCODING)
+            .and_then(|e| e.to_str().ok()),
+    );
+    // Each format and encoding is a different representation, so they need different ETags
+    let etag_suffix = match encoding {
+        Some(encoding) => format!("{}-{}", format.etag_suffix(), encoding.name()),
+        None => format.etag_suffix().to_string(),
+    };
+
+    match get_snapshot(storage, dataset).await {
+        Ok(Some(snapshot)) => {
+            let not_modified = req
+                .headers()
+                .get(header::IF_NONE_MATCH)
+                .and_then(|e| e.to_str().ok())
+                .is_some_and(|e| snapshot.matches(&etag_suffix, e));
+
+            let response = Response::builder()
+                .header(header::ETAG, snapshot.etag(&etag_suffix))
+                .header(header::VARY, "Accept, Accept-Encoding")
+                .header(header::LAST_MODIFIED, snapshot.last_modified());
+            if not_modified {
+                return Ok(response
+                    .status(StatusCode::NOT_MODIFIED)
+                    .body(bytes_body(Bytes::new()))
+                    .unwrap());
+            }
+
+            match filter(snapshot.data.clone()) {
+                Ok(Some(data)) => {
+                    let response = response
+                        .status(StatusCode::OK)
+                        .header(header::CONTENT_TYPE, format.content_type());
+                    // Unfiltered snapshots are compressed once and cached until the next update, and other bodies are compressed while they are sent
+                    let Some(encoding) = encoding.filter(|_| data == snapshot.data) else {
+                        return Ok(response.body(bytes_body(data)).unwrap());
+                    };
+
+                    match snapshot.compressed(encoding).await {
+                        Ok(compressed) => Ok(response
+                            .header(header::CONTENT_ENCODING, encoding.name())
+                            .body(bytes_body(compressed))
+                            .unwrap()),
+                        Err(e) => internal_error(&format!(
+                            "Error when compressing {}: {}",
+                            dataset.name(),
+                            e
+                        )),
+                    }
+                }
+                Ok(None) => not_found(),
+                Err(e) => internal_error(&format!("Error when parsing {}: {}", dataset.name(), e)),
+            }
+        }
+        Ok(None) => internal_error(&format!("The {} have not been updated yet", dataset.name())),
+        Err(e) => internal_error(&format!("Error when reading {}: {}", dataset.name(), e)),
+    }
+}
+
+/* Gets the metrics in the Prometheus text format */
+async fn metrics(auth: &Auth) -> Result<Response<BoxBody<Bytes, BoxError>>, Error> {
+    if !auth.valid {
+        return unauthorized();
     }
 
     Ok(Response::builder()
         .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, "application/json")
-        .body(file_body(file_result))
+        .header(header::CONTENT_TYPE, "text/plain; version=0.0.4")
+        .body(bytes_body(render().await.into()))
         .unwrap())
 }
 
-async fn base(config: Arc<Config>) -> Result<Response<BoxBody<Bytes, Error>>, Error> {
+async fn base(config: Arc<Config>) -> Result<Response<BoxBody<Bytes, BoxError>>, Error> {
     Ok(Response::builder()
         .status(StatusCode::OK)
         .header(header::CONTENT_TYPE, "application/json")
@@ -1057,6 +2236,8 @@ async fn base(config: Arc<Config>) -> Result<Response<BoxBody<Bytes, Error>>, Er
                 "underbin": config.is_enabled(Feature::Underbin),
                 "average_auction":config.is_enabled(Feature::AverageAuction),
                 "average_bin":config.is_enabled(Feature::AverageBin),
+                "sales":config.is_enabled(Feature::Sales),
+                "lowestbin_history":config.is_enabled(Feature::LowestbinHistory),
             },
             "statistics": {
                 "is_updating":*IS_UPDATING.lock().await,
@@ -1067,6 +2248,425 @@ async fn base(config: Arc<Config>) -> Result<Response<BoxBody<Bytes, Error>>, Er
         .unwrap())
 }
 
+/* Starts a query in a read only transaction that is canceled after QUERY_TIMEOUT, as QUERY_DATABASE_ROLE if it is set */
+async fn read_only_query(
+    config: &Config,
+    database: &StreamClient,
+    sql: &str,
+    params: &[&(dyn ToSql + Sync)],
+) -> Result<RowStream, tokio_postgres::Error> {
+    let client = database.client();
+    let mut begin = format!(
+        "BEGIN READ ONLY; SET LOCAL statement_timeout = {}",
+        config.query_timeout
+    );
+    if !config.query_database_role.is_empty() {
+        begin.push_str(&format!(
+            "; SET LOCAL ROLE \"{}\"",
+            config.query_database_role.replace('"', "\"\"")
+        ));
+    }
+    client.batch_execute(&begin).await?;
+    client.query_raw(sql, params.iter().copied()).await
+}
+
+/* Starts an admin query in a read only transaction with a timeout and records it in the audit log */
+async fn admin_query(
+    config: &Config,
+    database: &StreamClient,
+    name: &str,
+    query: &str,
+    sql: &str,
+    params: &[&(dyn ToSql + Sync)],
+) -> Result<RowStream, tokio_postgres::Error> {
+    let results = read_only_query(config, database, sql, params).await;
+
+    // The transaction is read only, so the audit log is written with another client
+    let _ = get_client()
+        .await
+        .execute(
+            "INSERT INTO audit_log (time_t, api_key, query, error) VALUES ($1, $2, $3, $4)",
+            &[
+                &(get_timestamp_millis() as i64),
+                &name,
+                &query,
+                &results.as_ref().err().map(|e| e.to_string()),
+            ],
+        )
+        .await;
+
+    results
+}
+
+/// Database client of a streamed response, kept until the last row is sent
+struct StreamClient {
+    client: Option<Client>,
+    /// If the client is in a transaction that is committed after the last row
+    transaction: bool,
+}
+
+impl StreamClient {
+    fn new(client: Client, transaction: bool) -> Self {
+        Self {
+            client: Some(client),
+            transaction,
+        }
+    }
+
+    fn client(&self) -> &Client {
+        self.client.as_ref().unwrap()
+    }
+
+    /* Commits the transaction (if any) and returns the client to the pool */
+    async fn finish(mut self) -> Result<(), tokio_postgres::Error> {
+        if let Some(client) = self.client.take() {
+            if self.transaction {
+                client.batch_execute("COMMIT").await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for StreamClient {
+    fn drop(&mut self) {
+        // The response ended early (e.g. the request was canceled or a row failed) in the middle of the
+        // transaction, so close the connection instead of returning it to the pool
+        if let Some(client) = self.client.take() {
+            if self.transaction {
+                let _ = Object::take(client);
+            }
+        }
+    }
+}
+
+/// Parameters of /query and /sales, which filter by the columns the query and ended_auctions tables both have
+struct AuctionParams {
+    sort_by: String,
+    sort_order: String,
+    limit: i64,
+    item_name: String,
+    item_id: String,
+    internal_id: String,
+    enchants: Vec<String>,
+    attributes: Vec<String>,
+    /// Only auctions ending after this epoch timestamp (-1 if not set)
+    end: i64,
+    bin: Option<bool>,
+    potato_books: i16,
+    stars: i16,
+    farming_for_dummies: i16,
+    transmission_tuner: i16,
+    mana_disintegrator: i16,
+    reforge: String,
+    rune: String,
+    skin: String,
+    power_scroll: String,
+    drill_upgrade_module: String,
+    drill_fuel_tank: String,
+    drill_engine: String,
+    dye: String,
+    accessory_enrichment: String,
+    recombobulated: Option<bool>,
+    wood_singularity: Option<bool>,
+    art_of_war: Option<bool>,
+    art_of_peace: Option<bool>,
+    etherwarp: Option<bool>,
+    necron_scrolls: Vec<String>,
+    gemstones: Vec<String>,
+}
+
+impl Default for AuctionParams {
+    fn default() -> Self {
+        Self {
+            sort_by: String::new(),
+            sort_order: String::new(),
+            limit: 1,
+            item_name: String::new(),
+            item_id: String::new(),
+            internal_id: String::new(),
+            enchants: Vec::new(),
+            attributes: Vec::new(),
+            end: -1,
+            bin: None,
+            potato_books: -1,
+            stars: -1,
+            farming_for_dummies: -1,
+            transmission_tuner: -1,
+            mana_disintegrator: -1,
+            reforge: String::new(),
+            rune: String::new(),
+            skin: String::new(),
+            power_scroll: String::new(),
+            drill_upgrade_module: String::new(),
+            drill_fuel_tank: String::new(),
+            drill_engine: String::new(),
+            dye: String::new(),
+            accessory_enrichment: String::new(),
+            recombobulated: None,
+            wood_singularity: None,
+            art_of_war: None,
+            art_of_peace: None,
+            etherwarp: None,
+            necron_scrolls: Vec::new(),
+            gemstones: Vec::new(),
+        }
+    }
+}
+
+impl AuctionParams {
+    /* Stores a query parameter if it is one of these parameters, or returns the error message if it could not be parsed */
+    fn read(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name {
+            "sort_by" => self.sort_by = value.to_string(),
+            "sort_order" => self.sort_order = value.to_string(),
+            "limit" => self.limit = parse_param(name, value)?,
+            "item_name" => self.item_name = value.to_string(),
+            "item_id" => self.item_id = value.to_string(),
+            "internal_id" => self.internal_id = value.to_string(),
+            "enchants" => self.enchants = split_param(value),
+            "attributes" => self.attributes = split_param(value),
+            "end" => self.end = parse_param(name, value)?,
+            "bin" => self.bin = Some(parse_param(name, value)?),
+            "potato_books" => self.potato_books = parse_param(name, value)?,
+            "stars" => self.stars = parse_param(name, value)?,
+            "farming_for_dummies" => self.farming_for_dummies = parse_param(name, value)?,
+            "transmission_tuner" => self.transmission_tuner = parse_param(name, value)?,
+            "mana_disintegrator" => self.mana_disintegrator = parse_param(name, value)?,
+            "reforge" => self.reforge = value.to_string(),
+            "rune" => self.rune = value.to_string(),
+            "skin" => self.skin = value.to_string(),
+            "power_scroll" => self.power_scroll = value.to_string(),
+            "drill_upgrade_module" => self.drill_upgrade_module = value.to_string(),
+            "drill_fuel_tank" => self.drill_fuel_tank = value.to_string(),
+            "drill_engine" => self.drill_engine = value.to_string(),
+            "dye" => self.dye = value.to_string(),
+            "accessory_enrichment" => self.accessory_enrichment = value.to_string(),
+            "recombobulated" => self.recombobulated = Some(parse_param(name, value)?),
+            "wood_singularity" => self.wood_singularity = Some(parse_param(name, value)?),
+            "art_of_war" => self.art_of_war = Some(parse_param(name, value)?),
+            "art_of_peace" => self.art_of_peace = Some(parse_param(name, value)?),
+            "etherwarp" => self.etherwarp = Some(parse_param(name, value)?),
+            "necron_scrolls" => self.necron_scrolls = split_param(value),
+            "gemstones" => self.gemstones = split_param(value),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /* If the limit is at most MAX_LIMIT or the key is an admin key */
+    fn limit_allowed(&self, auth: &Auth) -> bool {
+        (self.limit > 0 && self.limit <= MAX_LIMIT) || auth.admin
+    }
+
+    /* Adds the filters to the WHERE clause, or to the score when sorting by query. The item id, end, and item name always have to match, so they are added to where_sql when sorting by query */
+    fn push_filters<'a>(
+        &'a self,
+        sql: &mut String,
+        where_sql: &mut String,
+        param_vec: &mut Vec<&'a (dyn ToSql + Sync)>,
+        mut param_count: i32,
+        sort_by_query: bool,
+    ) -> i32 {
+        for (name, value) in [
+            ("stars", &self.stars),
+            ("potato_books", &self.potato_books),
+            ("farming_for_dummies", &self.farming_for_dummies),
+            ("transmission_tuner", &self.transmission_tuner),
+            ("mana_disintegrator", &self.mana_disintegrator),
+        ] {
+            param_count = int_eq(sql, param_vec, name, value, param_count, sort_by_query);
+        }
+        for (name, value) in [
+            ("reforge", &self.reforge),
+            ("rune", &self.rune),
+            ("skin", &self.skin),
+            ("dye", &self.dye),
+            ("internal_id", &self.internal_id),
+            ("power_scroll", &self.power_scroll),
+            ("drill_upgrade_module", &self.drill_upgrade_module),
+            ("drill_fuel_tank", &self.drill_fuel_tank),
+            ("drill_engine", &self.drill_engine),
+            ("accessory_enrichment", &self.accessory_enrichment),
+        ] {
+            param_count = str_eq(sql, param_vec, name, value, param_count, sort_by_query);
+        }
+        for (name, value) in [
+            ("bin", &self.bin),
+            ("recombobulated", &self.recombobulated),
+            ("wood_singularity", &self.wood_singularity),
+            ("art_of_war", &self.art_of_war),
+            ("art_of_peace", &self.art_of_peace),
+            ("etherwarp", &self.etherwarp),
+        ] {
+            param_count = bool_eq(sql, param_vec, name, value, param_count, sort_by_query);
+        }
+        for (name, value) in [
+            ("enchants", &self.enchants),
+            ("attributes", &self.attributes),
+            ("necron_scrolls", &self.necron_scrolls),
+            ("gemstones", &self.gemstones),
+        ] {
+            if !value.is_empty() {
+                param_count =
+                    array_contains(sql, param_vec, name, value, param_count, sort_by_query);
+            }
+        }
+
+        let required: [(&str, &'a (dyn ToSql + Sync), bool); 3] = [
+            ("item_id =", &self.item_id, !self.item_id.is_empty()),
+            ("end_t >", &self.end, self.end >= 0),
+            (
+                "item_name ILIKE",
+                &self.item_name,
+                !self.item_name.is_empty(),
+            ),
+        ];
+        for (condition, value, _) in required.into_iter().filter(|e| e.2) {
+            if sort_by_query {
+                if !where_sql.is_empty() {
+                    where_sql.push_str(" AND");
+                }
+                where_sql.push_str(&format!(" {} ${}", condition, param_count));
+            } else {
+                if param_count != 1 {
+                    sql.push_str(" AND");
+                }
+                sql.push_str(&format!(" {} ${}", condition, param_count));
+            }
+            param_vec.push(value);
+            param_count += 1;
+        }
+
+        param_count
+    }
+}
+
+/* Parses a query parameter, or returns the error message of the bad request */
+fn parse_param<T: FromStr>(name: &str, value: &str) -> Result<T, String>
+where
+    T::Err: std::fmt::Display,
+{
+    value
+        .parse()
+        .map_err(|e| format!("Error parsing {} parameter: {}", name, e))
+}
+
+/* Splits a comma separated query parameter, where an empty parameter has no values */
+fn split_param(value: &str) -> Vec<String> {
+    if value.is_empty() {
+        return Vec::new();
+    }
+    value.split(',').map(|s| s.trim().to_string()).collect()
+}
+
+/// A column /query or /sales can be sorted by
+struct SortKey {
+    name: String,
+    column: &'static str,
+    /// ASC or DESC
+    order: String,
+}
+
+impl SortKey {
+    /* Gets the value of the sort column for an auction */
+    fn value(&self, item: &QueryDatabaseItem) -> Value {
+        match self.name.as_str() {
+            "starting_bid" => json!(item.starting_bid),
+            "highest_bid" => json!(item.highest_bid),
+            "cur_bid" => json!(item.starting_bid.max(item.highest_bid)),
+            "end_t" => json!(item.end_t),
+            "lowestbin_price" => json!(item.lowestbin_price),
+            "stars" => json!(item.stars),
+            _ => Value::Null,
+        }
+    }
+
+    fn to_param(&self, value: &Value) -> Option<FilterParam> {
+        match self.name.as_str() {
+            "lowestbin_price" => value.as_f64().map(|e| Box::new(e as f32) as FilterParam),
+            "stars" => None,
+            _ => value.as_i64().map(|e| Box::new(e) as FilterParam),
+        }
+    }
+}
+
+/* Column of each key /query can be sorted by */
+fn query_sort_column(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "starting_bid" => "starting_bid",
+        "highest_bid" => "highest_bid",
+        "cur_bid" => "GREATEST(starting_bid, highest_bid)",
+        "end_t" => "end_t",
+        "lowestbin_price" => "lowestbin_price",
+        "stars" => "stars",
+        _ => return None,
+    })
+}
+
+/* Column of each key /sales can be sorted by */
+fn sales_sort_column(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "price" => "price",
+        "end_t" => "end_t",
+        _ => return None,
+    })
+}
+
+/* Parses comma separated sort keys and orders (one for each key or one for all), where an empty sort_by is unsorted and an empty sort_order is ASC */
+fn parse_sort(
+    sort_by: &str,
+    sort_order: &str,
+    sort_column: fn(&str) -> Option<&'static str>,
+) -> Result<Vec<SortKey>, String> {
+    if sort_by.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let names = sort_by.split(',').map(|e| e.trim()).collect::<Vec<&str>>();
+    let orders = if sort_order.is_empty() {
+        vec!["ASC"]
+    } else {
+        sort_order
+            .split(',')
+            .map(|e| e.trim())
+            .collect::<Vec<&str>>()
+    };
+    if orders.len() != 1 && orders.len() != names.len() {
+        return Err(String::from(
+            "The sort_order parameter must have one order or one for each sort_by key",
+        ));
+    }
+
+    let mut sort_keys = Vec::new();
+    for (i, name) in names.iter().enumerate() {
+        let column = match sort_column(name) {
+            Some(column) => column,
+            None => return Err(format!("Invalid sort_by key: {}", name)),
+        };
+        let order = orders[if orders.len() == 1 { 0 } else { i }];
+        if order != "ASC" && order != "DESC" {
+            return Err(format!("Invalid sort_order: {}", order));
+        }
+
+        sort_keys.push(SortKey {
+            name: name.to_string(),
+            column,
+            order: order.to_string(),
+        });
+    }
+
+    Ok(sort_keys)
+}
+
+fn order_by(sort_keys: &[SortKey]) -> String {
+    sort_keys
+        .iter()
+        .map(|e| format!("{} {} NULLS LAST", e.column, e.order))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
 fn bool_eq<'a>(
     sql: &mut String,
     param_vec: &mut Vec<&'a (dyn ToSql + Sync)>,
@@ -1133,6 +2733,31 @@ fn str_eq<'a>(
     param_count
 }
 
+/* Compares a value (e.g. min_stars or max_unit_price) if it was provided */
+fn int_cmp<'a, T: ToSql + Sync>(
+    sql: &mut String,
+    param_vec: &mut Vec<&'a (dyn ToSql + Sync)>,
+    param_name: &str,
+    operator: &str,
+    param_value: &'a Option<T>,
+    param_count: i32,
+    sort_by_query: bool,
+) -> i32 {
+    if let Some(param_value) = param_value {
+        return param_cmp(
+            sql,
+            param_vec,
+            param_name,
+            operator,
+            param_value,
+            param_count,
+            sort_by_query,
+        );
+    }
+
+    param_count
+}
+
 fn param_eq<'a>(
     sql: &mut String,
     param_vec: &mut Vec<&'a (dyn ToSql + Sync)>,
@@ -1140,6 +2765,26 @@ fn param_eq<'a>(
     param_value: &'a (dyn ToSql + Sync),
     param_count: i32,
     sort_by_query: bool,
+) -> i32 {
+    param_cmp(
+        sql,
+        param_vec,
+        param_name,
+        "=",
+        param_value,
+        param_count,
+        sort_by_query,
+    )
+}
+
+fn param_cmp<'a>(
+    sql: &mut String,
+    param_vec: &mut Vec<&'a (dyn ToSql + Sync)>,
+    param_name: &str,
+    operator: &str,
+    param_value: &'a (dyn ToSql + Sync),
+    param_count: i32,
+    sort_by_query: bool,
 ) -> i32 {
     if param_count != 1 {
         sql.push_str(if sort_by_query { " +" } else { " AND" });
@@ -1148,7 +2793,7 @@ fn param_eq<'a>(
         sql.push_str(" CASE WHEN")
     }
 
-    sql.push_str(&format!(" {} = ${}", param_name, param_count));
+    sql.push_str(&format!(" {} {} ${}", param_name, operator, param_count));
     param_vec.push(param_value);
 
     if sort_by_query {
@@ -1201,7 +2846,7 @@ fn array_contains<'a>(
     param_count_mut
 }
 
-fn http_err(status: StatusCode, reason: &str) -> Result<Response<BoxBody<Bytes, Error>>, Error> {
+fn http_err(status: StatusCode, reason: &str) -> Result<Response<BoxBody<Bytes, BoxError>>, Error> {
     Ok(Response::builder()
         .status(status)
         .header(header::CONTENT_TYPE, "application/json")
@@ -1209,27 +2854,50 @@ fn http_err(status: StatusCode, reason: &str) -> Result<Response<BoxBody<Bytes,
         .unwrap())
 }
 
-fn bad_request(reason: &str) -> Result<Response<BoxBody<Bytes, Error>>, Error> {
+fn bad_request(reason: &str) -> Result<Response<BoxBody<Bytes, BoxError>>, Error> {
     http_err(StatusCode::BAD_REQUEST, reason)
 }
 
-fn internal_error(reason: &str) -> Result<Response<BoxBody<Bytes, Error>>, Error> {
+fn internal_error(reason: &str) -> Result<Response<BoxBody<Bytes, BoxError>>, Error> {
     http_err(StatusCode::INTERNAL_SERVER_ERROR, reason)
 }
 
-fn unauthorized() -> Result<Response<BoxBody<Bytes, Error>>, Error> {
+fn unauthorized() -> Result<Response<BoxBody<Bytes, BoxError>>, Error> {
     http_err(StatusCode::UNAUTHORIZED, "Unauthorized")
 }
 
-fn not_found() -> Result<Response<BoxBody<Bytes, Error>>, Error> {
+/* Error response with the seconds until the request can be retried */
+fn retry_later(
+    status: StatusCode,
+    reason: &str,
+    retry_after: u64,
+) -> Result<Response<BoxBody<Bytes, BoxError>>, Error> {
+    let mut response = http_err(status, reason)?;
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, retry_after.into());
+    Ok(response)
+}
+
+fn not_found() -> Result<Response<BoxBody<Bytes, BoxError>>, Error> {
     http_err(StatusCode::NOT_FOUND, "Not found")
 }
 
-fn not_implemented() -> Result<Response<BoxBody<Bytes, Error>>, Error> {
-    http_err(StatusCode::NOT_IMPLEMENTED, "Unsupported method")
+fn not_implemented() -> Result<Response<BoxBody<Bytes, BoxError>>, Error> {
+    let mut response = http_err(StatusCode::NOT_IMPLEMENTED, "Unsupported method")?;
+    response
+        .headers_mut()
+        .insert(header::ALLOW, HeaderValue::from_static(ALLOWED_METHODS));
+    Ok(response)
+}
+
+fn empty_body() -> BoxBody<Bytes, BoxError> {
+    Full::new(Bytes::new())
+        .map_err(|never| match never {})
+        .boxed()
 }
 
-fn json_body<T>(json: &T) -> BoxBody<Bytes, Error>
+fn json_body<T>(json: &T) -> BoxBody<Bytes, BoxError>
 where
     T: ?Sized + Serialize,
 {
@@ -1238,7 +2906,171 @@ where
         .boxed()
 }
 
-fn file_body(file: Result<Vec<u8>, std::io::Error>) -> BoxBody<Bytes, Error> {
+/* Gets the format from the format parameter or the Accept header */
+fn request_format(req: &Request<impl Body>, format: &str) -> Result<Format, String> {
+    Format::negotiate(
+        format,
+        req.headers()
+            .get(header::ACCEPT)
+            .and_then(|e| e.to_str().ok()),
+    )
+}
+
+fn format_response(
+    format: Format,
+    body: Bytes,
+) -> Result<Response<BoxBody<Bytes, BoxError>>, Error> {
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, format.content_type())
+        .header(header::VARY, "Accept")
+        .body(bytes_body(body))
+        .unwrap())
+}
+
+/* Serializes a map as a JSON object or as rows with the key in the first column for other formats */
+fn map_body<M, V>(format: Format, map: M, columns: &[&str]) -> Bytes
+where
+    M: Serialize + IntoIterator<Item = (String, V)>,
+    V: Serialize,
+{
+    if format == Format::Json {
+        return serde_json::to_vec(&map).unwrap().into();
+    }
+
+    RowWriter::new(format, columns).write_all(map_rows(map, columns[0], columns[1]))
+}
+
+/* Streams rows from the database in the format as they are received, keeping the client until the last row */
+fn stream_response<T: Serialize + 'static>(
+    format: Format,
+    columns: &[&str],
+    client: StreamClient,
+    rows: RowStream,
+    map: impl Fn(Row) -> T + Send + Sync + 'static,
+) -> Result<Response<BoxBody<Bytes, BoxError>>, Error> {
+    let writer = RowWriter::new(format, columns);
+    let start = writer.start();
+    let end = writer.end();
+    stream_rows(format, writer, start, end, client, rows, map)
+}
+
+/* Streams a page of /query results, with the next cursor after the items in JSON or as a header otherwise */
+fn stream_page(
+    format: Format,
+    columns: &[&str],
+    client: StreamClient,
+    rows: RowStream,
+    next_cursor: Option<String>,
+) -> Result<Response<BoxBody<Bytes, BoxError>>, Error> {
+    let writer = RowWriter::new(format, columns);
+    if format == Format::Json {
+        let start = Bytes::from_static(b"{\"items\":[");
+        let end = Bytes::from(format!("],\"next_cursor\":{}}}", json!(next_cursor)));
+        return stream_rows(
+            format,
+            writer,
+            start,
+            end,
+            client,
+            rows,
+            QueryDatabaseItem::from,
+        );
+    }
+
+    let start = writer.start();
+    let end = writer.end();
+    let mut response = stream_rows(
+        format,
+        writer,
+        start,
+        end,
+        client,
+        rows,
+        QueryDatabaseItem::from,
+    )?;
+    if let Some(next_cursor) = next_cursor {
+        response
+            .headers_mut()
+            .insert("X-Next-Cursor", next_cursor.parse().unwrap());
+    }
+    Ok(response)
+}
+
+fn stream_rows<T: Serialize + 'static>(
+    format: Format,
+    mut writer: RowWriter,
+    start: Bytes,
+    end: Bytes,
+    client: StreamClient,
+    rows: RowStream,
+    map: impl Fn(Row) -> T + Send + Sync + 'static,
+) -> Result<Response<BoxBody<Bytes, BoxError>>, Error> {
+    let body = stream::once(async move { Ok(start) })
+        .chain(rows.map(move |row| row.map(|row| writer.row(&map(row))).map_err(BoxError::from)))
+        .chain(stream::once(async move {
+            client.finish().await?;
+            Ok(end)
+        }))
+        .map_ok(Frame::data);
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, format.content_type())
+        .header(header::VARY, "Accept")
+        .body(BodyExt::boxed(StreamBody::new(body)))
+        .unwrap())
+}
+
+/* Compresses the body while it is sent if the client accepts an encoding and the body is not already encoded or too small */
+fn compress_response(
+    encoding: Option<Encoding>,
+    response: Response<BoxBody<Bytes, BoxError>>,
+) -> Response<BoxBody<Bytes, BoxError>> {
+    let (mut parts, body) = response.into_parts();
+    if parts.headers.contains_key(header::CONTENT_ENCODING) {
+        return Response::from_parts(parts, body);
+    }
+    if !parts
+        .headers
+        .get_all(header::VARY)
+        .iter()
+        .any(|e| e.to_str().is_ok_and(|e| e.contains("Accept-Encoding")))
+    {
+        parts
+            .headers
+            .append(header::VARY, HeaderValue::from_static("Accept-Encoding"));
+    }
+
+    let Some(encoding) = encoding else {
+        return Response::from_parts(parts, body);
+    };
+    if parts.status == StatusCode::NOT_MODIFIED
+        || body
+            .size_hint()
+            .upper()
+            .is_some_and(|e| e < MIN_COMPRESS_SIZE)
+    {
+        return Response::from_parts(parts, body);
+    }
+
+    parts.headers.insert(
+        header::CONTENT_ENCODING,
+        HeaderValue::from_static(encoding.name()),
+    );
+    parts.headers.remove(header::CONTENT_LENGTH);
+    let reader = StreamReader::new(body.into_data_stream().map_err(std::io::Error::other));
+    let compressed = ReaderStream::new(encoding.encoder(reader, false))
+        .map_ok(Frame::data)
+        .map_err(BoxError::from);
+    Response::from_parts(parts, BodyExt::boxed(StreamBody::new(compressed)))
+}
+
+fn bytes_body(bytes: Bytes) -> BoxBody<Bytes, BoxError> {
+    Full::new(bytes).map_err(|never| match never {}).boxed()
+}
+
+fn file_body(file: Result<Vec<u8>, std::io::Error>) -> BoxBody<Bytes, BoxError> {
     Full::from(file.unwrap())
         .map_err(|never| match never {})
         .boxed()
@@ -16,13 +16,20 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use crate::webhook::Webhook;
+use crate::{
+    metrics::Histogram,
+    rate_limit::TokenBucket,
+    storage::{Dataset, Snapshot},
+    structs::{ApiKey, IngestError},
+    webhook::Webhook,
+};
+use dashmap::DashMap;
 use deadpool_postgres::Pool;
 use lazy_static::lazy_static;
 use postgres_types::Type;
 use regex::Regex;
-use std::time::Duration;
-use tokio::sync::Mutex;
+use std::{collections::HashMap, time::Duration};
+use tokio::sync::{Mutex, RwLock, Semaphore};
 
 lazy_static! {
     pub static ref HTTP_CLIENT: reqwest::Client = reqwest::ClientBuilder::new()
@@ -34,7 +41,20 @@ lazy_static! {
     pub static ref IS_UPDATING: Mutex<bool> = Mutex::new(false);
     pub static ref TOTAL_UPDATES: Mutex<i32> = Mutex::new(0);
     pub static ref LAST_UPDATED: Mutex<i64> = Mutex::new(0);
+    pub static ref INGEST_ERRORS: Mutex<Vec<IngestError>> = Mutex::new(Vec::new());
     pub static ref WEBHOOK: Mutex<Option<Webhook>> = Mutex::new(None);
     pub static ref BID_ARRAY: Mutex<Option<Type>> = Mutex::new(None);
     pub static ref DATABASE: Mutex<Option<Pool>> = Mutex::new(None);
+    pub static ref SNAPSHOTS: RwLock<HashMap<Dataset, Snapshot>> = RwLock::new(HashMap::new());
+    /// Price and time of the latest row of each internal id in the lowestbin_history table
+    pub static ref LOWESTBIN_HISTORY_LATEST: DashMap<String, (Option<f32>, i64)> = DashMap::new();
+    /// Keys from the api_keys table by the hash of the key
+    pub static ref API_KEYS: DashMap<String, ApiKey> = DashMap::new();
+    /// Token bucket of each client by API key name or IP
+    pub static ref RATE_LIMITS: DashMap<String, TokenBucket> = DashMap::new();
+    /// Permits for requests using database connections, which are added when the server starts
+    pub static ref DATABASE_REQUESTS: Semaphore = Semaphore::new(0);
+    /// Value of each counter and gauge by name and labels
+    pub static ref METRICS: DashMap<(&'static str, String), f64> = DashMap::new();
+    pub static ref HISTOGRAMS: DashMap<(&'static str, String), Histogram> = DashMap::new();
 }
@@ -0,0 +1,186 @@
+/*
+ * Rust Query API - A versatile API facade for the Hypixel Auction API
+ * Copyright (c) 2022 kr45732
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::{
+    compression::Encoding,
+    statics::SNAPSHOTS,
+    utils::{get_client, get_timestamp_millis},
+};
+use dashmap::DashMap;
+use hyper::body::Bytes;
+use serde::Serialize;
+use std::{
+    error::Error,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, UNIX_EPOCH},
+};
+use tokio::sync::OnceCell;
+
+/// Where the lowest bins, under bins, and query items are stored between updates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageBackend {
+    /// Only visible to this process and lost on restart
+    Memory,
+    /// Shared by every instance using the same database
+    Postgres,
+}
+
+impl FromStr for StorageBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "MEMORY" => Self::Memory,
+            "POSTGRES" => Self::Postgres,
+            _ => return Err(format!("Unknown storage backend {}", s)),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Dataset {
+    Lowestbin,
+    /// The auction holding each lowest bin
+    LowestbinDetails,
+    Underbin,
+    QueryItems,
+}
+
+impl Dataset {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Lowestbin => "lowestbin",
+            Self::LowestbinDetails => "lowestbin_details",
+            Self::Underbin => "underbin",
+            Self::QueryItems => "query_items",
+        }
+    }
+}
+
+/// A dataset serialized into JSON
+#[derive(Clone)]
+pub struct Snapshot {
+    pub data: Bytes,
+    /// When the snapshot was stored (epoch timestamp in milliseconds)
+    pub updated: i64,
+    /// Data compressed with each encoding, shared by clones of the snapshot
+    compressed: Arc<DashMap<Encoding, Arc<OnceCell<Bytes>>>>,
+}
+
+impl Snapshot {
+    /* Gets the ETag of a representation of the snapshot, where the suffix distinguishes formats */
+    pub fn etag(&self, suffix: &str) -> String {
+        format!("\"{}{}\"", self.updated, suffix)
+    }
+
+    pub fn last_modified(&self) -> String {
+        httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_millis(self.updated as u64))
+    }
+
+    /* Gets the data compressed with the encoding, which is only compressed once for each snapshot (concurrent requests wait for the same compression) */
+    pub async fn compressed(&self, encoding: Encoding) -> std::io::Result<Bytes> {
+        let cell = self.compressed.entry(encoding).or_default().clone();
+        cell.get_or_try_init(|| encoding.compress(self.data.clone()))
+            .await
+            .cloned()
+    }
+
+    /* If the If-None-Match header matches this snapshot (the client already has it) */
+    pub fn matches(&self, suffix: &str, if_none_match: &str) -> bool {
+        let etag = self.etag(suffix);
+        if_none_match
+            .split(',')
+            .map(|e| e.trim())
+            .any(|e| e == "*" || e.trim_start_matches("W/") == etag)
+    }
+}
+
+/* Serializes and stores the dataset, replacing the previous snapshot */
+pub async fn put_snapshot<T>(
+    backend: StorageBackend,
+    dataset: Dataset,
+    value: &T,
+) -> Result<(), Box<dyn Error + Send + Sync>>
+where
+    T: ?Sized + Serialize,
+{
+    let snapshot = Snapshot {
+        data: serde_json::to_vec(value)?.into(),
+        updated: get_timestamp_millis() as i64,
+        compressed: Arc::default(),
+    };
+
+    if backend == StorageBackend::Postgres {
+        let _ = get_client()
+            .await
+            .execute(
+                "INSERT INTO snapshots (name, data, updated) VALUES ($1, $2, $3)
+                    ON CONFLICT (name) DO UPDATE SET data = EXCLUDED.data, updated = EXCLUDED.updated",
+                &[&dataset.name(), &snapshot.data.as_ref(), &snapshot.updated],
+            )
+            .await?;
+    }
+
+    // Swapped in one step so readers never see a partially written snapshot
+    SNAPSHOTS.write().await.insert(dataset, snapshot);
+
+    Ok(())
+}
+
+/* Gets the latest snapshot of the dataset or None if it was never stored */
+pub async fn get_snapshot(
+    backend: StorageBackend,
+    dataset: Dataset,
+) -> Result<Option<Snapshot>, Box<dyn Error + Send + Sync>> {
+    let cached = SNAPSHOTS.read().await.get(&dataset).cloned();
+    if backend == StorageBackend::Memory {
+        return Ok(cached);
+    }
+
+    // Another instance may have stored a newer snapshot, so only reuse the cached one if it is still the latest
+    let database = get_client().await;
+    let updated: Option<i64> = database
+        .query_opt(
+            "SELECT updated FROM snapshots WHERE name = $1",
+            &[&dataset.name()],
+        )
+        .await?
+        .map(|row| row.get("updated"));
+    match (updated, cached) {
+        (None, _) => Ok(None),
+        (Some(updated), Some(cached)) if cached.updated == updated => Ok(Some(cached)),
+        _ => {
+            let snapshot = database
+                .query_opt(
+                    "SELECT data, updated FROM snapshots WHERE name = $1",
+                    &[&dataset.name()],
+                )
+                .await?
+                .map(|row| Snapshot {
+                    data: row.get::<_, Vec<u8>>("data").into(),
+                    updated: row.get("updated"),
+                    compressed: Arc::default(),
+                });
+            if let Some(snapshot) = &snapshot {
+                SNAPSHOTS.write().await.insert(dataset, snapshot.clone());
+            }
+            Ok(snapshot)
+        }
+    }
+}
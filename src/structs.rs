@@ -17,6 +17,7 @@
  */
 
 use crate::utils::{is_false, median};
+use base64::{engine::general_purpose, Engine};
 use dashmap::DashMap;
 use postgres_types::{FromSql, ToSql};
 use serde::{Deserialize, Serialize};
@@ -25,6 +26,47 @@ use std::collections::BTreeMap;
 use tokio_postgres::Row;
 
 /* Query API */
+/// Columns of query results in CSV, where sorting by query adds a score column after the uuid
+pub const QUERY_COLUMNS: [&str; 37] = [
+    "uuid",
+    "auctioneer",
+    "end_t",
+    "item_name",
+    "lore",
+    "tier",
+    "item_id",
+    "internal_id",
+    "starting_bid",
+    "highest_bid",
+    "bin",
+    "count",
+    "lowestbin_price",
+    "enchants",
+    "attributes",
+    "bids",
+    "potato_books",
+    "stars",
+    "farming_for_dummies",
+    "transmission_tuner",
+    "mana_disintegrator",
+    "reforge",
+    "rune",
+    "skin",
+    "power_scroll",
+    "drill_upgrade_module",
+    "drill_fuel_tank",
+    "drill_engine",
+    "dye",
+    "accessory_enrichment",
+    "recombobulated",
+    "wood_singularity",
+    "art_of_war",
+    "art_of_peace",
+    "etherwarp",
+    "necron_scrolls",
+    "gemstones",
+];
+
 #[derive(Serialize)]
 pub struct QueryDatabaseItem {
     pub uuid: String,
@@ -41,7 +83,7 @@ pub struct QueryDatabaseItem {
     pub highest_bid: i64,
     pub bin: bool,
     pub count: i16,
-    #[serde(skip_serializing)]
+    /// Price of one item
     pub lowestbin_price: f32,
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub enchants: Vec<String>,
@@ -138,6 +180,126 @@ impl From<Row> for QueryDatabaseItem {
     }
 }
 
+/// Position after the last auction of a page, sent to clients as an opaque token
+#[derive(Serialize, Deserialize)]
+pub struct QueryCursor {
+    /// Columns and orders the results were sorted by (e.g. stars DESC,starting_bid ASC)
+    pub sort: String,
+    /// Value of each sort column for the last auction
+    pub values: Vec<Value>,
+    pub uuid: String,
+}
+
+impl QueryCursor {
+    pub fn encode(&self) -> String {
+        general_purpose::URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap())
+    }
+
+    pub fn decode(cursor: &str) -> Option<Self> {
+        general_purpose::URL_SAFE_NO_PAD
+            .decode(cursor)
+            .ok()
+            .and_then(|e| serde_json::from_slice(&e).ok())
+    }
+}
+
+/* Sales API */
+#[derive(Serialize)]
+pub struct SalesDatabaseItem {
+    pub uuid: String,
+    pub end_t: i64,
+    pub item_name: String,
+    pub item_id: String,
+    pub internal_id: String,
+    pub price: i64,
+    pub count: i16,
+    pub bin: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub enchants: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub attributes: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub potato_books: Option<i16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stars: Option<i16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub farming_for_dummies: Option<i16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transmission_tuner: Option<i16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mana_disintegrator: Option<i16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reforge: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rune: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skin: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub power_scroll: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub drill_upgrade_module: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub drill_fuel_tank: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub drill_engine: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dye: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub accessory_enrichment: Option<String>,
+    #[serde(skip_serializing_if = "is_false")]
+    pub recombobulated: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    pub wood_singularity: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    pub art_of_war: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    pub art_of_peace: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    pub etherwarp: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub necron_scrolls: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gemstones: Option<Vec<String>>,
+}
+
+impl From<Row> for SalesDatabaseItem {
+    fn from(row: Row) -> Self {
+        Self {
+            uuid: row.get("uuid"),
+            end_t: row.get("end_t"),
+            item_name: row.get("item_name"),
+            item_id: row.get("item_id"),
+            internal_id: row.get("internal_id"),
+            price: row.get("price"),
+            count: row.get("count"),
+            bin: row.get("bin"),
+            enchants: row.get("enchants"),
+            attributes: row.get("attributes"),
+            potato_books: row.get("potato_books"),
+            stars: row.get("stars"),
+            farming_for_dummies: row.get("farming_for_dummies"),
+            transmission_tuner: row.get("transmission_tuner"),
+            mana_disintegrator: row.get("mana_disintegrator"),
+            reforge: row.get("reforge"),
+            rune: row.get("rune"),
+            skin: row.get("skin"),
+            power_scroll: row.get("power_scroll"),
+            drill_upgrade_module: row.get("drill_upgrade_module"),
+            drill_fuel_tank: row.get("drill_fuel_tank"),
+            drill_engine: row.get("drill_engine"),
+            dye: row.get("dye"),
+            accessory_enrichment: row.get("accessory_enrichment"),
+            recombobulated: row.get("recombobulated"),
+            wood_singularity: row.get("wood_singularity"),
+            art_of_war: row.get("art_of_war"),
+            art_of_peace: row.get("art_of_peace"),
+            etherwarp: row.get("etherwarp"),
+            necron_scrolls: row.get("necron_scrolls"),
+            gemstones: row.get("gemstones"),
+        }
+    }
+}
+
 #[derive(Debug, ToSql, FromSql, Deserialize, Serialize)]
 #[postgres(name = "bid")]
 pub struct Bid {
@@ -223,9 +385,31 @@ impl AvgSum {
         self.sum += sum;
         self.count += count;
     }
+}
 
-    pub fn get_average(&self) -> i64 {
-        self.sum / self.count as i64
+/* History API */
+#[derive(Serialize)]
+pub struct HistoryCandle {
+    pub time: i32,
+    pub open: f32,
+    pub high: f32,
+    pub low: f32,
+    pub close: f32,
+    pub mean: f32,
+    pub volume: f32,
+}
+
+impl From<Row> for HistoryCandle {
+    fn from(row: Row) -> Self {
+        Self {
+            time: row.get("time"),
+            open: row.get("open"),
+            high: row.get("high"),
+            low: row.get("low"),
+            close: row.get("close"),
+            mean: row.get("mean"),
+            volume: row.get("volume"),
+        }
     }
 }
 
@@ -234,17 +418,149 @@ impl AvgSum {
 pub struct PetsDatabaseItem {
     pub name: String,
     pub price: i64,
+    /// Sales of the pet since the time
+    pub count: i64,
 }
 
-impl From<Row> for PetsDatabaseItem {
+pub struct PetSalesDatabaseItem {
+    pub uuid: String,
+    pub end_t: i64,
+    /// Pet name without the level (e.g. WITHER_SKELETON)
+    pub name: String,
+    pub level: i16,
+    /// Rarity after tier boosting
+    pub tier: String,
+    pub tier_boost: bool,
+    pub held_item: Option<String>,
+    pub skin: Option<String>,
+    pub candy_used: i16,
+    pub price: i64,
+    pub bin: bool,
+}
+
+#[derive(Serialize)]
+pub struct PetLevelPrice {
+    pub level: i16,
+    pub price: f64,
+    pub sales: i64,
+    /// If there were no sales of this level, so the price was interpolated from the closest levels with sales
+    pub interpolated: bool,
+}
+
+/* Lowest Bin API */
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LowestBin {
+    pub price: f32,
+    /// UUID of the auction currently holding the lowest bin
+    pub uuid: String,
+    pub auctioneer: String,
+}
+
+/* Lowest Bin History API */
+#[derive(Serialize)]
+pub struct LowestbinHistoryItem {
+    pub time_t: i64,
+    /// None if there were no bins of the item from this time
+    pub price: Option<f32>,
+}
+
+impl From<Row> for LowestbinHistoryItem {
     fn from(row: Row) -> Self {
         Self {
-            name: row.get("name"),
+            time_t: row.get("time_t"),
             price: row.get("price"),
         }
     }
 }
 
+/* Ingest Errors API */
+#[derive(Serialize)]
+pub struct IngestError {
+    pub uuid: String,
+    pub reason: String,
+}
+
+/* API Keys API */
+#[derive(Serialize, Clone)]
+pub struct ApiKey {
+    pub name: String,
+    /// SHA-256 hash of the key, since keys are only shown when they are created
+    #[serde(skip)]
+    pub key_hash: String,
+    pub admin: bool,
+    /// Paths the key can use (e.g. /query), where an empty list allows every endpoint
+    pub endpoints: Vec<String>,
+    /// When the key stops working (epoch timestamp in milliseconds)
+    pub expires_at: Option<i64>,
+    /// Max requests per UTC day
+    pub daily_quota: Option<i64>,
+    pub created: i64,
+}
+
+impl ApiKey {
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at.is_some_and(|e| e <= now)
+    }
+
+    /* If the key can use the path, where an endpoint also allows the paths under it */
+    pub fn allows(&self, path: &str) -> bool {
+        self.endpoints.is_empty()
+            || self.endpoints.iter().any(|e| {
+                path == e
+                    || path
+                        .strip_prefix(e.as_str())
+                        .is_some_and(|e| e.starts_with('/'))
+            })
+    }
+}
+
+impl From<Row> for ApiKey {
+    fn from(row: Row) -> Self {
+        Self {
+            name: row.get("name"),
+            key_hash: row.get("key_hash"),
+            admin: row.get("admin"),
+            endpoints: row.get("endpoints"),
+            expires_at: row.get("expires_at"),
+            daily_quota: row.get("daily_quota"),
+            created: row.get("created"),
+        }
+    }
+}
+
+/// Body of requests creating or updating an API key
+#[derive(Deserialize)]
+pub struct ApiKeyRequest {
+    pub name: String,
+    #[serde(default)]
+    pub admin: bool,
+    #[serde(default)]
+    pub endpoints: Vec<String>,
+    pub expires_at: Option<i64>,
+    pub daily_quota: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct ApiKeyUsage {
+    pub name: String,
+    /// UTC day (e.g. 2024-01-31)
+    pub day: String,
+    /// Route pattern of the endpoint (e.g. /lowestbin/{item_id})
+    pub endpoint: String,
+    pub requests: i64,
+}
+
+impl From<Row> for ApiKeyUsage {
+    fn from(row: Row) -> Self {
+        Self {
+            name: row.get("name"),
+            day: row.get("day"),
+            endpoint: row.get("endpoint"),
+            requests: row.get("requests"),
+        }
+    }
+}
+
 /* NBT */
 #[derive(Deserialize)]
 pub struct PartialNbt {
@@ -303,6 +619,30 @@ pub struct PartialExtraAttr {
 }
 
 impl PartialExtraAttr {
+    pub fn get_enchants(&self) -> Vec<String> {
+        let mut out = Vec::new();
+        if let Some(enchantments) = &self.enchantments {
+            for entry in enchantments {
+                out.push(format!("{};{}", entry.key().to_uppercase(), entry.value()));
+            }
+        }
+        out
+    }
+
+    pub fn get_attributes(&self) -> Vec<String> {
+        let mut out = Vec::new();
+        if let Some(attributes) = &self.attributes {
+            for entry in attributes {
+                out.push(format!(
+                    "ATTRIBUTE_SHARD_{};{}",
+                    entry.0.to_uppercase(),
+                    entry.1
+                ));
+            }
+        }
+        out
+    }
+
     pub fn is_shiny(&self) -> bool {
         if let Some(is_shiny_value) = &self.is_shiny {
             return is_shiny_value == &1;
@@ -444,6 +784,9 @@ pub struct PetInfo {
     pub tier: String,
     #[serde(rename = "heldItem")]
     pub held_item: Option<String>,
+    pub skin: Option<String>,
+    #[serde(rename = "candyUsed")]
+    pub candy_used: Option<i16>,
 }
 
 #[derive(Deserialize)]
@@ -481,6 +824,7 @@ pub struct EndedAuctions {
 
 #[derive(Deserialize)]
 pub struct EndedAuction {
+    pub timestamp: i64,
     pub price: i64,
     pub bin: bool,
     pub item_bytes: String,
@@ -16,7 +16,13 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use crate::{config::Config, statics::*, structs::*};
+use crate::{
+    config::Config,
+    metrics::{record_insert, record_rows},
+    statics::*,
+    storage::{put_snapshot, Dataset, StorageBackend},
+    structs::*,
+};
 use base64::{engine::general_purpose, Engine};
 use dashmap::{DashMap, DashSet};
 use deadpool_postgres::Client;
@@ -26,8 +32,8 @@ use postgres_types::{ToSql, Type};
 use serde_json::Value;
 use std::{
     cmp::Ordering,
+    collections::HashMap,
     fmt::Write,
-    fs::OpenOptions,
     sync::{Arc, Mutex},
     thread,
     time::{Instant, SystemTime, UNIX_EPOCH},
@@ -36,13 +42,13 @@ use tokio::time::{self, Duration};
 use tokio_postgres::{binary_copy::BinaryCopyInWriter, Error};
 
 /* Repeat a task */
-pub async fn start_auction_loop<F, Fut>(mut f: F)
+pub async fn start_auction_loop<F, Fut>(config: Arc<Config>, mut f: F)
 where
     F: Send + 'static + FnMut() -> Fut,
     Fut: Future<Output = ()> + Send + 'static,
 {
     // Create stream of intervals.
-    let mut interval = time::interval(get_duration_until_api_update().await);
+    let mut interval = time::interval(get_duration_until_api_update(&config).await);
     tokio::spawn(async move {
         loop {
             // Skip tick at 0ms
@@ -52,19 +58,22 @@ where
             // Spawn a task for this tick.
             f().await;
             // Updated to new interval
-            interval = time::interval(get_duration_until_api_update().await);
+            interval = time::interval(get_duration_until_api_update(&config).await);
         }
     });
 }
 
 /* Gets the time until the next API update according to Cloudflare headers */
-async fn get_duration_until_api_update() -> Duration {
+async fn get_duration_until_api_update(config: &Config) -> Duration {
     let mut num_attempts = 0;
     loop {
         num_attempts += 1;
 
         if let Ok(res) = HTTP_CLIENT
-            .get("https://api.hypixel.net/skyblock/auctions?page=0")
+            .get(format!(
+                "{}/skyblock/auctions?page=0",
+                config.hypixel_api_base
+            ))
             .send()
             .await
         {
@@ -140,11 +149,12 @@ pub fn error(desc: String) {
     });
 }
 
-pub fn parse_nbt(data: &str) -> Option<PartialNbt> {
-    general_purpose::STANDARD
+pub fn parse_nbt(data: &str) -> Result<PartialNbt, String> {
+    let bytes = general_purpose::STANDARD
         .decode(data)
-        .ok()
-        .and_then(|bytes| nbt::from_gzip_reader::<_, PartialNbt>(std::io::Cursor::new(bytes)).ok())
+        .map_err(|e| format!("Unable to decode item bytes: {}", e))?;
+    nbt::from_gzip_reader::<_, PartialNbt>(std::io::Cursor::new(bytes))
+        .map_err(|e| format!("Unable to parse item NBT: {}", e))
 }
 
 pub fn calculate_with_taxes(price: f32) -> f32 {
@@ -167,23 +177,30 @@ pub fn calculate_with_taxes(price: f32) -> f32 {
     price * (1.0 - tax)
 }
 
-pub fn valid_api_key(config: Arc<Config>, key: String, admin_only: bool) -> bool {
-    if config.admin_api_key == key {
-        return true;
-    }
-    if admin_only {
-        return false;
-    }
-    config.api_key.is_empty() || (key == config.api_key)
-}
-
-pub fn update_lower_else_insert(id: &str, starting_bid: f32, prices: &DashMap<String, f32>) {
+pub fn update_lower_else_insert(
+    id: &str,
+    starting_bid: f32,
+    uuid: &str,
+    auctioneer: &str,
+    prices: &DashMap<String, LowestBin>,
+) {
     if let Some(mut ele) = prices.get_mut(id) {
-        if starting_bid < *ele {
-            *ele = starting_bid;
+        if starting_bid < ele.price {
+            *ele = LowestBin {
+                price: starting_bid,
+                uuid: uuid.to_string(),
+                auctioneer: auctioneer.to_string(),
+            };
         }
     } else {
-        prices.insert(id.to_string(), starting_bid);
+        prices.insert(
+            id.to_string(),
+            LowestBin {
+                price: starting_bid,
+                uuid: uuid.to_string(),
+                auctioneer: auctioneer.to_string(),
+            },
+        );
     }
 }
 
@@ -191,11 +208,15 @@ pub async fn update_query_bin_underbin_fn(
     auctions: Mutex<Vec<QueryDatabaseItem>>,
     ended_auction_uuids: DashSet<String>,
     is_full_update: bool,
-    bin_prices: &DashMap<String, f32>,
+    bin_prices: &DashMap<String, LowestBin>,
     update_lowestbin: bool,
     last_updated: i64,
     update_underbin: bool,
     under_bin_prices: &DashMap<String, Value>,
+    storage: StorageBackend,
+    update_lowestbin_history: bool,
+    lowestbin_history_retention: i64,
+    started_epoch: i64,
 ) -> (String, String) {
     let mut ok_logs = String::new();
     let mut err_logs = String::new();
@@ -208,61 +229,156 @@ pub async fn update_query_bin_underbin_fn(
         bin_prices,
         update_lowestbin,
         last_updated,
+        storage,
     )
     .await
     {
-        Ok(rows) => write!(
-            ok_logs,
-            "\nSuccessfully inserted {} query auctions into database in {}ms",
-            rows,
-            query_started.elapsed().as_millis()
-        ),
-        Err(e) => write!(err_logs, "\nError inserting query into database: {}", e),
+        Ok(rows) => {
+            record_insert("query", query_started.elapsed().as_secs_f64(), true);
+            record_rows("query", rows);
+            write!(
+                ok_logs,
+                "\nSuccessfully inserted {} query auctions into database in {}ms",
+                rows,
+                query_started.elapsed().as_millis()
+            )
+        }
+        Err(e) => {
+            record_insert("query", query_started.elapsed().as_secs_f64(), false);
+            write!(err_logs, "\nError inserting query into database: {}", e)
+        }
     };
 
     if update_lowestbin {
         let bins_started = Instant::now();
-        let _ = match update_bins_local(bin_prices).await {
+        let result = update_bins_storage(storage, bin_prices).await;
+        record_insert(
+            "lowestbin",
+            bins_started.elapsed().as_secs_f64(),
+            result.is_ok(),
+        );
+        let _ = match result {
             Ok(_) => write!(
                 ok_logs,
-                "\nSuccessfully updated bins file in {}ms",
+                "\nSuccessfully updated bins in {}ms",
                 bins_started.elapsed().as_millis()
             ),
-            Err(e) => write!(err_logs, "\nError updating bins file: {}", e),
+            Err(e) => write!(err_logs, "\nError updating bins: {}", e),
         };
 
         if update_underbin {
             let under_bins_started = Instant::now();
-            let _ = match update_under_bins_local(under_bin_prices).await {
+            let result = update_under_bins_storage(storage, under_bin_prices).await;
+            record_insert(
+                "underbin",
+                under_bins_started.elapsed().as_secs_f64(),
+                result.is_ok(),
+            );
+            let _ = match result {
                 Ok(_) => write!(
                     ok_logs,
-                    "\nSuccessfully updated under bins file in {}ms",
+                    "\nSuccessfully updated under bins in {}ms",
                     under_bins_started.elapsed().as_millis()
                 ),
                 Err(e) => {
-                    write!(err_logs, "\nError updating under bins file: {}", e)
+                    write!(err_logs, "\nError updating under bins: {}", e)
                 }
             };
         }
+
+        if update_lowestbin_history {
+            let history_started = Instant::now();
+            let result = update_lowestbin_history_database(
+                bin_prices,
+                lowestbin_history_retention,
+                started_epoch,
+            )
+            .await;
+            record_insert(
+                "lowestbin_history",
+                history_started.elapsed().as_secs_f64(),
+                result.is_ok(),
+            );
+            if let Ok(rows) = result {
+                record_rows("lowestbin_history", rows);
+            }
+            let _ = match result {
+                Ok(rows) => write!(
+                    ok_logs,
+                    "\nSuccessfully inserted {} lowest bin changes into database in {}ms",
+                    rows,
+                    history_started.elapsed().as_millis()
+                ),
+                Err(e) => write!(
+                    err_logs,
+                    "\nError inserting lowest bin history into database: {}",
+                    e
+                ),
+            };
+        }
     }
 
     (ok_logs, err_logs)
 }
 
-pub async fn update_pets_fn(pet_prices: DashMap<String, AvgSum>) -> (String, String) {
-    let pets_started = Instant::now();
-    match update_pets_database(pet_prices).await {
+pub async fn update_pet_sales_fn(
+    pet_sales: Mutex<Vec<PetSalesDatabaseItem>>,
+    retention: i64,
+    time_t: i64,
+) -> (String, String) {
+    let pet_sales_started = Instant::now();
+    let result = update_pet_sales_database(pet_sales, retention, time_t).await;
+    record_insert(
+        "pet_sales",
+        pet_sales_started.elapsed().as_secs_f64(),
+        result.is_ok(),
+    );
+    if let Ok(rows) = result {
+        record_rows("pet_sales", rows);
+    }
+    match result {
+        Ok(rows) => (
+            format!(
+                "\nSuccessfully inserted {} pet sales into database in {}ms",
+                rows,
+                pet_sales_started.elapsed().as_millis()
+            ),
+            String::new(),
+        ),
+        Err(e) => (
+            String::new(),
+            format!("\nError inserting pet sales into database: {}", e),
+        ),
+    }
+}
+
+pub async fn update_sales_fn(
+    sale_prices: Mutex<Vec<SalesDatabaseItem>>,
+    retention: i64,
+    time_t: i64,
+) -> (String, String) {
+    let sales_started = Instant::now();
+    let result = update_sales_database(sale_prices, retention, time_t).await;
+    record_insert(
+        "sales",
+        sales_started.elapsed().as_secs_f64(),
+        result.is_ok(),
+    );
+    if let Ok(rows) = result {
+        record_rows("ended_auctions", rows);
+    }
+    match result {
         Ok(rows) => (
             format!(
-                "\nSuccessfully inserted {} pets into database in {}ms",
+                "\nSuccessfully inserted {} sales into database in {}ms",
                 rows,
-                pets_started.elapsed().as_millis()
+                sales_started.elapsed().as_millis()
             ),
             String::new(),
         ),
         Err(e) => (
             String::new(),
-            format!("\nError inserting pets into database: {}", e),
+            format!("\nError inserting sales into database: {}", e),
         ),
     }
 }
@@ -272,9 +388,16 @@ pub async fn update_average_fn(
     table: &str,
     avg_prices: DashMap<String, AvgSum>,
     time_t: i64,
+    retention: i64,
 ) -> (String, String) {
     let avg_started = Instant::now();
-    match update_avgerage_database(table, avg_prices, (time_t / 1000) as i32).await {
+    let result =
+        update_avgerage_database(table, avg_prices, (time_t / 1000) as i32, retention as i32).await;
+    record_insert(table, avg_started.elapsed().as_secs_f64(), result.is_ok());
+    if let Ok(rows) = result {
+        record_rows(table, rows);
+    }
+    match result {
         Ok(count) => (
             format!(
                 "\nSuccessfully inserted {} {} into database in {}ms",
@@ -295,9 +418,10 @@ async fn update_query_database(
     mut auctions: Mutex<Vec<QueryDatabaseItem>>,
     ended_auction_uuids: DashSet<String>,
     is_full_update: bool,
-    bin_prices: &DashMap<String, f32>,
+    bin_prices: &DashMap<String, LowestBin>,
     update_lowestbin: bool,
     last_updated: i64,
+    storage: StorageBackend,
 ) -> Result<u64, Error> {
     let database = get_client().await;
 
@@ -310,7 +434,7 @@ async fn update_query_database(
             .iter()
             .map(|o| o.item_name.to_string())
             .collect::<DashSet<String>>();
-        update_query_items_local(query_names);
+        update_query_items_storage(storage, query_names).await;
     } else {
         // Remove ended auctions and duplicate 'new' auctions
         let mut delete_uuids = ended_auction_uuids
@@ -440,7 +564,7 @@ async fn update_query_database(
         let mut all_auctions_sql = String::from("SELECT item_name");
         // These fields are only needed to update lowest bin
         if update_lowestbin {
-            all_auctions_sql.push_str(", internal_id, lowestbin_price, bin");
+            all_auctions_sql.push_str(", uuid, auctioneer, internal_id, lowestbin_price, bin");
         }
         all_auctions_sql.push_str(" FROM query");
 
@@ -452,53 +576,205 @@ async fn update_query_database(
             if update_lowestbin && ele.get("bin") {
                 let internal_id: String = ele.get("internal_id");
                 let lowestbin_price: f32 = ele.get("lowestbin_price");
-                update_lower_else_insert(&internal_id, lowestbin_price, bin_prices);
+                let uuid: String = ele.get("uuid");
+                let auctioneer: String = ele.get("auctioneer");
+                update_lower_else_insert(
+                    &internal_id,
+                    lowestbin_price,
+                    &uuid,
+                    &auctioneer,
+                    bin_prices,
+                );
             }
         }
 
-        update_query_items_local(query_names);
+        update_query_items_storage(storage, query_names).await;
     }
 
     Ok(rows_added)
 }
 
-async fn update_pets_database(pet_prices: DashMap<String, AvgSum>) -> Result<u64, Error> {
+async fn update_pet_sales_database(
+    mut pet_sales: Mutex<Vec<PetSalesDatabaseItem>>,
+    retention: i64, // In days
+    time_t: i64,    // In milliseconds
+) -> Result<u64, Error> {
     let database = get_client().await;
 
-    // Add all old pet prices to the new prices if the new prices doesn't have that old pet name
-    let old_pet_prices = database.query("SELECT * FROM pets", &[]).await?;
-    for old_pet in old_pet_prices {
-        let old_name: String = old_pet.get("name");
-        let old_count: i32 = old_pet.get("count");
-        let old_price: i64 = old_pet.get("price");
-        let old_sum: i64 = old_price * (old_count as i64);
+    // Delete pet sales older than the retention
+    tokio::spawn(async move {
+        let _ = get_client()
+            .await
+            .execute(
+                "DELETE FROM pet_sales WHERE end_t < $1",
+                &[&(time_t - retention * 86400000)],
+            )
+            .await;
+    });
 
-        if let Some(mut value) = pet_prices.get_mut(&old_name) {
-            value.update(old_sum, old_count);
-        } else {
-            pet_prices.insert(
-                old_name,
-                AvgSum {
-                    sum: old_sum,
-                    count: old_count,
-                },
-            );
-        }
+    // The ended auctions endpoint can return the same auction in consecutive updates
+    let uuids = pet_sales
+        .get_mut()
+        .unwrap()
+        .iter()
+        .map(|o| o.uuid.to_string())
+        .collect::<Vec<String>>();
+    let _ = database
+        .execute("DELETE FROM pet_sales WHERE uuid = ANY($1)", &[&uuids])
+        .await?;
+
+    let copy_statement = database.prepare("COPY pet_sales FROM STDIN BINARY").await?;
+    let copy_sink = database.copy_in(&copy_statement).await?;
+    let copy_writer = BinaryCopyInWriter::new(
+        copy_sink,
+        &[
+            Type::TEXT,
+            Type::INT8,
+            Type::TEXT,
+            Type::INT2,
+            Type::TEXT,
+            Type::BOOL,
+            Type::TEXT,
+            Type::TEXT,
+            Type::INT2,
+            Type::INT8,
+            Type::BOOL,
+        ],
+    );
+    pin_mut!(copy_writer);
+
+    // Write to copy sink
+    for m in pet_sales.get_mut().unwrap().iter() {
+        let row: Vec<&'_ (dyn ToSql + Sync)> = vec![
+            &m.uuid,
+            &m.end_t,
+            &m.name,
+            &m.level,
+            &m.tier,
+            &m.tier_boost,
+            &m.held_item,
+            &m.skin,
+            &m.candy_used,
+            &m.price,
+            &m.bin,
+        ];
+
+        copy_writer.as_mut().write(&row).await?;
     }
 
-    let _ = database.simple_query("TRUNCATE TABLE pets").await;
+    copy_writer.finish().await
+}
+
+async fn update_sales_database(
+    mut sale_prices: Mutex<Vec<SalesDatabaseItem>>,
+    retention: i64, // In days
+    time_t: i64,    // In milliseconds
+) -> Result<u64, Error> {
+    let database = get_client().await;
+
+    // Delete sales older than the retention
+    tokio::spawn(async move {
+        let _ = get_client()
+            .await
+            .execute(
+                "DELETE FROM ended_auctions WHERE end_t < $1",
+                &[&(time_t - retention * 86400000)],
+            )
+            .await;
+    });
+
+    // The ended auctions endpoint can return the same auction in consecutive updates
+    let uuids = sale_prices
+        .get_mut()
+        .unwrap()
+        .iter()
+        .map(|o| o.uuid.to_string())
+        .collect::<Vec<String>>();
+    let _ = database
+        .execute("DELETE FROM ended_auctions WHERE uuid = ANY($1)", &[&uuids])
+        .await?;
 
-    let copy_statement = database.prepare("COPY pets FROM STDIN BINARY").await?;
+    let copy_statement = database
+        .prepare("COPY ended_auctions FROM STDIN BINARY")
+        .await?;
     let copy_sink = database.copy_in(&copy_statement).await?;
-    let copy_writer = BinaryCopyInWriter::new(copy_sink, &[Type::TEXT, Type::INT8, Type::INT4]);
+
+    let copy_writer = BinaryCopyInWriter::new(
+        copy_sink,
+        &[
+            Type::TEXT,
+            Type::INT8,
+            Type::TEXT,
+            Type::TEXT,
+            Type::TEXT,
+            Type::INT8,
+            Type::INT2,
+            Type::BOOL,
+            Type::TEXT_ARRAY,
+            Type::TEXT_ARRAY,
+            Type::INT2,
+            Type::INT2,
+            Type::INT2,
+            Type::INT2,
+            Type::INT2,
+            Type::TEXT,
+            Type::TEXT,
+            Type::TEXT,
+            Type::TEXT,
+            Type::TEXT,
+            Type::TEXT,
+            Type::TEXT,
+            Type::TEXT,
+            Type::TEXT,
+            Type::BOOL,
+            Type::BOOL,
+            Type::BOOL,
+            Type::BOOL,
+            Type::BOOL,
+            Type::TEXT_ARRAY,
+            Type::TEXT_ARRAY,
+        ],
+    );
+
     pin_mut!(copy_writer);
 
     // Write to copy sink
-    for m in pet_prices.iter() {
-        copy_writer
-            .as_mut()
-            .write(&[m.key(), &m.value().get_average(), &m.value().count])
-            .await?;
+    for m in sale_prices.get_mut().unwrap().iter() {
+        let row: Vec<&'_ (dyn ToSql + Sync)> = vec![
+            &m.uuid,
+            &m.end_t,
+            &m.item_name,
+            &m.item_id,
+            &m.internal_id,
+            &m.price,
+            &m.count,
+            &m.bin,
+            &m.enchants,
+            &m.attributes,
+            &m.potato_books,
+            &m.stars,
+            &m.farming_for_dummies,
+            &m.transmission_tuner,
+            &m.mana_disintegrator,
+            &m.reforge,
+            &m.rune,
+            &m.skin,
+            &m.power_scroll,
+            &m.drill_upgrade_module,
+            &m.drill_fuel_tank,
+            &m.drill_engine,
+            &m.dye,
+            &m.accessory_enrichment,
+            &m.recombobulated,
+            &m.wood_singularity,
+            &m.art_of_war,
+            &m.art_of_peace,
+            &m.etherwarp,
+            &m.necron_scrolls,
+            &m.gemstones,
+        ];
+
+        copy_writer.as_mut().write(&row).await?;
     }
 
     copy_writer.finish().await
@@ -507,12 +783,13 @@ async fn update_pets_database(pet_prices: DashMap<String, AvgSum>) -> Result<u64
 async fn update_avgerage_database(
     table: &str,
     avg_prices: DashMap<String, AvgSum>,
-    time_t: i32, // In seconds
+    time_t: i32,    // In seconds
+    retention: i32, // In days
 ) -> Result<u64, Error> {
     let table_str = table.to_string();
     let database = get_client().await;
 
-    // Delete averages older than 7 days
+    // Delete averages older than the retention
     tokio::spawn(async move {
         let _ = get_client()
             .await
@@ -520,7 +797,7 @@ async fn update_avgerage_database(
                 &format!(
                     "DELETE FROM {} WHERE time_t < {}",
                     table_str,
-                    time_t - 604800 // 7 days (in seconds)
+                    time_t - retention * 86400
                 )
                 .to_string(),
             )
@@ -554,14 +831,92 @@ async fn update_avgerage_database(
     copy_writer.finish().await
 }
 
-async fn update_bins_local(bin_prices: &DashMap<String, f32>) -> Result<(), serde_json::Error> {
+async fn update_lowestbin_history_database(
+    bin_prices: &DashMap<String, LowestBin>,
+    retention: i64, // In days
+    time_t: i64,    // In milliseconds
+) -> Result<u64, Error> {
+    let database = get_client().await;
+
+    // Delete history older than the retention
+    tokio::spawn(async move {
+        let _ = get_client()
+            .await
+            .execute(
+                "DELETE FROM lowestbin_history WHERE time_t < $1",
+                &[&(time_t - retention * 86400000)],
+            )
+            .await;
+    });
+
+    // The latest rows are only read from the database after a restart, then kept as they are inserted
+    if LOWESTBIN_HISTORY_LATEST.is_empty() {
+        for row in database
+            .query(
+                "SELECT DISTINCT ON (internal_id) internal_id, price, time_t FROM lowestbin_history ORDER BY internal_id, time_t DESC",
+                &[],
+            )
+            .await?
+        {
+            LOWESTBIN_HISTORY_LATEST
+                .insert(row.get("internal_id"), (row.get("price"), row.get("time_t")));
+        }
+    }
+
+    // Only insert lowest bins that changed since they were last inserted, or whose latest row is about to be
+    // deleted by the retention (so the history still has the current price)
+    let expiring_before = time_t - retention * 86400000 / 2;
+    let mut changes: Vec<(String, Option<f32>)> = Vec::new();
+    for ele in bin_prices {
+        let price = Some(ele.value().price);
+        if LOWESTBIN_HISTORY_LATEST
+            .get(ele.key())
+            .map_or(true, |e| e.0 != price || e.1 < expiring_before)
+        {
+            changes.push((ele.key().to_owned(), price));
+        }
+    }
+    // Items without any bins left have a price of null
+    for ele in LOWESTBIN_HISTORY_LATEST.iter() {
+        if ele.0.is_some() && !bin_prices.contains_key(ele.key()) {
+            changes.push((ele.key().to_owned(), None));
+        }
+    }
+
+    let copy_statement = database
+        .prepare("COPY lowestbin_history FROM STDIN BINARY")
+        .await?;
+    let copy_sink = database.copy_in(&copy_statement).await?;
+    let copy_writer = BinaryCopyInWriter::new(copy_sink, &[Type::TEXT, Type::INT8, Type::FLOAT4]);
+    pin_mut!(copy_writer);
+
+    for (internal_id, price) in &changes {
+        copy_writer
+            .as_mut()
+            .write(&[internal_id, &time_t, price])
+            .await?;
+    }
+    let rows = copy_writer.finish().await?;
+
+    for (internal_id, price) in changes {
+        LOWESTBIN_HISTORY_LATEST.insert(internal_id, (price, time_t));
+    }
+    Ok(rows)
+}
+
+async fn update_bins_storage(
+    storage: StorageBackend,
+    bin_prices: &DashMap<String, LowestBin>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     // Calculate lowestbin of item (regardless of attributes)
     let additional_prices = DashMap::new();
     for ele in bin_prices {
         if ele.key().contains("+ATTRIBUTE_SHARD_") {
             update_lower_else_insert(
                 ele.key().split("+ATTRIBUTE_SHARD_").next().unwrap(),
-                *ele.value(),
+                ele.value().price,
+                &ele.value().uuid,
+                &ele.value().auctioneer,
                 &additional_prices,
             );
         }
@@ -570,35 +925,23 @@ async fn update_bins_local(bin_prices: &DashMap<String, f32>) -> Result<(), serd
         bin_prices.insert(ele.0, ele.1);
     }
 
-    let file = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .truncate(true)
-        .open("lowestbin.json")
-        .unwrap();
-    serde_json::to_writer(file, bin_prices)
+    let prices = bin_prices
+        .iter()
+        .map(|e| (e.key().to_string(), e.value().price))
+        .collect::<HashMap<String, f32>>();
+    put_snapshot(storage, Dataset::Lowestbin, &prices).await?;
+    put_snapshot(storage, Dataset::LowestbinDetails, bin_prices).await
 }
 
-async fn update_under_bins_local(
+async fn update_under_bins_storage(
+    storage: StorageBackend,
     bin_prices: &DashMap<String, Value>,
-) -> Result<(), serde_json::Error> {
-    let file = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .truncate(true)
-        .open("underbin.json")
-        .unwrap();
-    serde_json::to_writer(file, &bin_prices)
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    put_snapshot(storage, Dataset::Underbin, bin_prices).await
 }
 
-fn update_query_items_local(query_prices: DashSet<String>) {
-    let file = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .truncate(true)
-        .open("query_items.json")
-        .unwrap();
-    let _ = serde_json::to_writer(file, &query_prices);
+async fn update_query_items_storage(storage: StorageBackend, query_prices: DashSet<String>) {
+    let _ = put_snapshot(storage, Dataset::QueryItems, &query_prices).await;
 }
 
 pub async fn get_client() -> Client {
@@ -666,6 +1009,50 @@ fn partition(data: &[f32]) -> (Vec<f32>, f32, Vec<f32>) {
     (left, pivot, right)
 }
 
+/* Fills in levels without sales by linearly interpolating between the closest levels with sales */
+pub fn interpolate_pet_levels(
+    sold_levels: &[(i16, f64, i64)],
+    levels: &[i16],
+) -> Vec<PetLevelPrice> {
+    let mut prices = Vec::new();
+    if sold_levels.is_empty() {
+        return prices;
+    }
+
+    for &level in levels {
+        // sold_levels is sorted by level
+        let index = sold_levels.partition_point(|e| e.0 < level);
+        let lower = index.checked_sub(1).map(|i| sold_levels[i]);
+        let upper = sold_levels.get(index).copied();
+
+        prices.push(match (lower, upper) {
+            (_, Some(upper)) if upper.0 == level => PetLevelPrice {
+                level,
+                price: upper.1,
+                sales: upper.2,
+                interpolated: false,
+            },
+            (Some(lower), Some(upper)) => PetLevelPrice {
+                level,
+                price: lower.1
+                    + (upper.1 - lower.1) * (level - lower.0) as f64 / (upper.0 - lower.0) as f64,
+                sales: 0,
+                interpolated: true,
+            },
+            // Outside the range of sold levels, so use the closest one
+            (Some(closest), None) | (None, Some(closest)) => PetLevelPrice {
+                level,
+                price: closest.1,
+                sales: 0,
+                interpolated: true,
+            },
+            (None, None) => unreachable!(),
+        });
+    }
+
+    prices
+}
+
 pub fn update_average_map(map: &DashMap<String, AvgSum>, id: &str, price: i64, count: i16) {
     // If the map already has this id, then add to the existing elements, otherwise create a new entry
     if let Some(mut value) = map.get_mut(id) {
@@ -59,6 +59,12 @@ impl EmbedBuilder {
     }
 }
 
+impl Default for EmbedBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct Embed {
     title: Option<String>,
@@ -101,6 +107,12 @@ impl Message {
     }
 }
 
+impl Default for Message {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct Webhook {
     url: String,
 }